@@ -5,6 +5,66 @@ pub enum Literal {
     Ident(String),
     Number(f64),
     Ref(String),
+    /// Runtime-tunable parameter: `$threshold`
+    Param(String),
+    /// Quoted string: `"abc"`
+    Str(String),
+    /// Time span: `250ns`, `10us`, `500ms`, `30s`, `5m`, `1h`
+    Duration(f64, TimeUnit),
+}
+
+/// Unit of a time span or of `TimeStep::time`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq)]
+pub enum TimeUnit {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+    Minutes,
+    Hours,
+}
+
+impl TimeUnit {
+    /// Returns the length of a single unit in nanoseconds
+    pub fn nanos(&self) -> u64 {
+        match self {
+            TimeUnit::Nanoseconds => 1,
+            TimeUnit::Microseconds => 1_000,
+            TimeUnit::Milliseconds => 1_000_000,
+            TimeUnit::Seconds => 1_000_000_000,
+            TimeUnit::Minutes => 60_000_000_000,
+            TimeUnit::Hours => 3_600_000_000_000,
+        }
+    }
+
+    /// Converts a time span given in this unit into the `other` unit.
+    /// The ratio of units is an exact fraction, the value is divided last.
+    pub fn convert(&self, value: f64, other: TimeUnit) -> f64 {
+        let (num, den) = (self.nanos(), other.nanos());
+        let gcd = gcd(num, den);
+        value * (num / gcd) as f64 / (den / gcd) as f64
+    }
+}
+
+impl Display for TimeUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeUnit::Nanoseconds => write!(f, "ns"),
+            TimeUnit::Microseconds => write!(f, "us"),
+            TimeUnit::Milliseconds => write!(f, "ms"),
+            TimeUnit::Seconds => write!(f, "s"),
+            TimeUnit::Minutes => write!(f, "m"),
+            TimeUnit::Hours => write!(f, "h"),
+        }
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq)]
@@ -38,7 +98,7 @@ impl Operation {
             Operation::Minus => lhs - rhs,
             Operation::Times => lhs * rhs,
             Operation::Divide => lhs / rhs,
-            Operation::Power => rhs.powf(lhs),
+            Operation::Power => lhs.powf(rhs),
             _ => unimplemented!(), //Operation::Factorial => (rhs as usize..1).fold(1.0, |acc, val| acc * val as f64),
         }
     }
@@ -47,7 +107,7 @@ impl Operation {
     pub fn unary_eval(&self, rhs: f64) -> f64 {
         match self {
             Operation::Factorial => (rhs as usize..1).fold(1.0, |acc, val| acc * val as f64),
-            Operation::Minus => -1.0 * rhs,
+            Operation::Minus => -rhs,
             _ => rhs,
         }
     }
@@ -62,6 +122,53 @@ pub enum Expression {
     },
     BinOp(Box<Expression>, Box<Expression>, Operation),
     UnOp(Operation, Box<Expression>),
+    /// Array literal: `[1, 2, 3]`
+    Array(Vec<Expression>),
+    /// Array element access: `weights[1]`
+    Index(Box<Expression>, Box<Expression>),
+    /// Field access: `&last.price`
+    Field(Box<Expression>, String),
+    /// Function argument passed by name: `len=20`
+    NamedArg(String, Box<Expression>),
+    /// Local name bound to a value inside of the body:
+    /// `let spread = &ask - &bid in spread / 2`
+    Let {
+        name: String,
+        value: Box<Expression>,
+        body: Box<Expression>,
+    },
+    /// Inline function: `|acc, x| acc + x`
+    Lambda {
+        params: Vec<String>,
+        body: Box<Expression>,
+    },
+}
+
+/// Item defined in the DSL
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Definition {
+    /// Function: `def zscore(x, m, s) = (x - m) / s`
+    Function {
+        name: String,
+        params: Vec<String>,
+        body: Expression,
+    },
+    /// Constant folded into a value at build time: `const K = 2 * PI`
+    Const { name: String, body: Expression },
+}
+
+impl Definition {
+    pub fn name(&self) -> &str {
+        match self {
+            Definition::Function { name, .. } | Definition::Const { name, .. } => name,
+        }
+    }
+
+    pub fn body(&self) -> &Expression {
+        match self {
+            Definition::Function { body, .. } | Definition::Const { body, .. } => body,
+        }
+    }
 }
 
 /// Provides a Visitor pattern interface to the Expression
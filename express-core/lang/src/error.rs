@@ -0,0 +1,52 @@
+use std::fmt::Display;
+
+/// Describes why and where the source failed to parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// Byte offset of the failure in the source
+    pub offset: usize,
+    /// Line of the failure starting from 1
+    pub line: usize,
+    /// Column of the failure in characters starting from 1
+    pub column: usize,
+    pub message: String,
+    /// Possible fix of the error
+    pub hint: Option<String>,
+}
+
+impl SyntaxError {
+    /// Creates an error located at `rest` which is a suffix of the `source`
+    pub fn at(source: &str, rest: &str, message: impl Into<String>) -> Self {
+        let offset = source.len() - rest.len();
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+        Self {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub fn with_hint(mut self, hint: Option<impl Into<String>>) -> Self {
+        self.hint = hint.map(Into::into);
+        self
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.line, self.column
+        )?;
+        if let Some(hint) = &self.hint {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SyntaxError {}
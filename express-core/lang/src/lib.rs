@@ -1,2 +1,5 @@
 pub mod ast;
+pub mod error;
+pub mod operators;
 pub mod parser;
+pub mod printer;
@@ -0,0 +1,150 @@
+use crate::ast::{Expression, Literal, Operation};
+
+/// Grouping of infix operators of the same precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a ** b ** c` is `a ** (b ** c)`
+    Right,
+}
+
+/// Placement of an operator relative to its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixity {
+    Prefix,
+    Postfix,
+    Infix(Assoc),
+}
+
+/// What an operator is parsed into
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpKind {
+    /// Arithmetic evaluated by the interpreter itself
+    Builtin(Operation),
+    /// Call of a function with the operands as arguments: `a %% b` => `fn(a, b)`
+    Call(String),
+}
+
+/// Entry of the [OperatorTable]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator {
+    pub symbol: String,
+    pub fixity: Fixity,
+    /// Operators with higher precedence bind tighter
+    pub precedence: u8,
+    pub kind: OpKind,
+}
+
+impl Operator {
+    pub fn new(symbol: &str, fixity: Fixity, precedence: u8, kind: OpKind) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            fixity,
+            precedence,
+            kind,
+        }
+    }
+
+    /// Tells if the operator is a word like `mod` rather than a symbol
+    pub(crate) fn is_word(&self) -> bool {
+        self.symbol.chars().all(|c| c.is_alphanumeric() || c == '_')
+    }
+
+    /// Builds an expression of the operator applied to operands
+    pub fn apply(&self, mut operands: Vec<Expression>) -> Expression {
+        match (&self.kind, operands.len()) {
+            (OpKind::Builtin(Operation::Minus), 1) => match operands.pop().unwrap() {
+                // keeps negative literals as they are
+                Expression::Const(Literal::Number(num)) => Expression::Const(Literal::Number(-num)),
+                operand => Expression::UnOp(Operation::Minus, Box::new(operand)),
+            },
+            (OpKind::Builtin(op), 1) => Expression::UnOp(*op, Box::new(operands.pop().unwrap())),
+            (OpKind::Builtin(op), _) => {
+                let rhs = operands.pop().unwrap();
+                let lhs = operands.pop().unwrap();
+                Expression::BinOp(Box::new(lhs), Box::new(rhs), *op)
+            }
+            (OpKind::Call(name), _) => Expression::Function {
+                name: Literal::Ident(name.clone()),
+                args: operands,
+            },
+        }
+    }
+}
+
+/// Operators known to the parser along with their precedence
+/// and associativity. Embedders may extend it with operators
+/// that call their own functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperatorTable {
+    operators: Vec<Operator>,
+}
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        use Operation::*;
+        let builtin = |symbol, fixity, precedence, op| {
+            Operator::new(symbol, fixity, precedence, OpKind::Builtin(op))
+        };
+        Self {
+            operators: vec![
+                builtin("+", Fixity::Infix(Assoc::Left), 10, Plus),
+                builtin("-", Fixity::Infix(Assoc::Left), 10, Minus),
+                builtin("*", Fixity::Infix(Assoc::Left), 20, Times),
+                builtin("/", Fixity::Infix(Assoc::Left), 20, Divide),
+                builtin("-", Fixity::Prefix, 30, Minus),
+                builtin("!", Fixity::Prefix, 30, Factorial),
+                builtin("**", Fixity::Infix(Assoc::Right), 40, Power),
+            ],
+        }
+    }
+}
+
+impl OperatorTable {
+    /// Adds an operator. Prefix operators may share symbols with
+    /// infix or postfix ones, since they are met in other positions.
+    pub fn add(&mut self, operator: Operator) -> Result<(), String> {
+        let symbol = operator.symbol.as_str();
+        let reserved = ['(', ')', '[', ']', ',', '"', '#', '.', '$'];
+        if symbol.is_empty()
+            || symbol == "="
+            || symbol.contains(|c: char| c.is_whitespace() || reserved.contains(&c))
+            || symbol.starts_with("//")
+            || symbol.starts_with("/*")
+            || (operator.fixity == Fixity::Prefix && symbol.starts_with(['&', '|']))
+        {
+            return Err(format!("Operator '{}' conflicts with the syntax", symbol));
+        }
+        let is_prefix = operator.fixity == Fixity::Prefix;
+        if self
+            .operators
+            .iter()
+            .any(|op| op.symbol == symbol && (op.fixity == Fixity::Prefix) == is_prefix)
+        {
+            return Err(format!("Operator '{}' is already defined", symbol));
+        }
+        self.operators.push(operator);
+        Ok(())
+    }
+
+    /// Finds the longest operator the input starts with.
+    /// Looks for prefix operators or for infix and postfix ones.
+    pub fn find(&self, input: &str, prefix: bool) -> Option<&Operator> {
+        self.operators
+            .iter()
+            .filter(|op| (op.fixity == Fixity::Prefix) == prefix)
+            .filter(|op| {
+                input.starts_with(op.symbol.as_str())
+                    && !(op.is_word()
+                        && input[op.symbol.len()..]
+                            .starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+            })
+            .max_by_key(|op| op.symbol.len())
+    }
+
+    /// Returns operators in the order they were added
+    pub fn operators(&self) -> &[Operator] {
+        &self.operators
+    }
+}
@@ -1,166 +1,368 @@
 #![allow(dead_code)]
 use crate::ast::*;
-use nom::bytes::complete::{tag, take_while1};
+use crate::error::SyntaxError;
+use crate::operators::{Assoc, Fixity, OperatorTable};
+use nom::bytes::complete::{is_not, tag, take_until, take_while1, take_while_m_n};
 use nom::character::is_alphanumeric;
 use nom::{
     branch::alt,
-    character::complete::{char, multispace0},
-    combinator::{cut, map},
-    error::context,
-    multi::{fold_many0, separated_list0},
+    character::complete::{char, multispace1, not_line_ending, satisfy},
+    combinator::{cut, map, map_opt, not, opt, recognize, value},
+    error::{context, VerboseError, VerboseErrorKind},
+    multi::{fold_many0, many0, many0_count, separated_list0},
     number::complete::double,
-    sequence::{delimited, pair, preceded},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
+/// Result of a parser that keeps contexts of failures
+pub type ParseResult<'i, T> = IResult<&'i str, T, VerboseError<&'i str>>;
 
-/// Parses number as a floating point. Any fp notation is valid
-fn parse_number(input: &str) -> IResult<&str, Literal> {
-    map(double, |num: f64| Literal::Number(num))(input)
+/// Skips whitespace along with comments: __# note__, __// note__, __/* note */__.
+/// Comments stay in the input, so byte offsets of the following tokens are kept.
+fn ws(input: &str) -> ParseResult<'_, &str> {
+    recognize(many0_count(alt((
+        multispace1,
+        recognize(pair(alt((tag("#"), tag("//"))), not_line_ending)),
+        recognize(pair(
+            tag("/*"),
+            context("closing comment", cut(pair(take_until("*/"), tag("*/")))),
+        )),
+    ))))(input)
+}
+
+/// Parses number as a floating point. Any fp notation is valid.
+/// A number followed by a unit is a time span: __500ms__, __5m__.
+/// The sign is left to prefix operators.
+fn parse_number(input: &str) -> ParseResult<'_, Literal> {
+    let unit = terminated(
+        alt((
+            value(TimeUnit::Nanoseconds, tag("ns")),
+            value(TimeUnit::Microseconds, alt((tag("us"), tag("µs")))),
+            value(TimeUnit::Milliseconds, tag("ms")),
+            value(TimeUnit::Seconds, tag("s")),
+            value(TimeUnit::Minutes, tag("m")),
+            value(TimeUnit::Hours, tag("h")),
+        )),
+        not(satisfy(_is_valid_ident)),
+    );
+    let unsigned = preceded(not(satisfy(|c| c == '+' || c == '-')), double);
+    map(pair(unsigned, opt(unit)), |(num, unit)| match unit {
+        Some(unit) => Literal::Duration(num, unit),
+        None => Literal::Number(num),
+    })(input)
 }
 
 fn _is_valid_ident(chr: char) -> bool {
     is_alphanumeric(chr as u8) || chr == '_'
 }
 
-/// Parses any given identifier which is alphabetic
+/// Parses any given identifier which is alphabetic.
+/// Identifiers of namespaces are separated by __::__
 /// ```ignore
 /// assert_eq!(parse_ident("abc"), Ok(("", Literal::Ident(String("abc")))))
+/// assert_eq!(parse_ident("ta::ema"), Ok(("", Literal::Ident(String("ta::ema")))))
 /// assert_eq!(parse_ident("1abc"), Err(...))
 /// ```
-fn parse_ident(input: &str) -> IResult<&str, Literal> {
-    map(take_while1(_is_valid_ident), |ident: &str| {
-        Literal::Ident(ident.to_string())
-    })(input)
+fn parse_ident(input: &str) -> ParseResult<'_, Literal> {
+    let path = recognize(pair(
+        take_while1(_is_valid_ident),
+        many0_count(preceded(
+            tag("::"),
+            context("name after '::'", cut(take_while1(_is_valid_ident))),
+        )),
+    ));
+    map(path, |ident: &str| Literal::Ident(ident.to_string()))(input)
 }
 
 /// Parses reference ident like: __&some_name__
-fn parse_reference(input: &str) -> IResult<&str, Literal> {
+fn parse_reference(input: &str) -> ParseResult<'_, Literal> {
     map(
         preceded(char('&'), take_while1(_is_valid_ident)),
         |ident: &str| Literal::Ref(ident.to_string()),
     )(input)
 }
 
-fn parse_literal(input: &str) -> IResult<&str, Literal> {
-    alt((parse_number, parse_ident, parse_reference))(input)
+/// Parses parameter slot like: __$threshold__
+fn parse_param(input: &str) -> ParseResult<'_, Literal> {
+    map(
+        preceded(
+            char('$'),
+            context(
+                "parameter name after '$'",
+                cut(take_while1(_is_valid_ident)),
+            ),
+        ),
+        |ident: &str| Literal::Param(ident.to_string()),
+    )(input)
+}
+
+/// Parses an escape sequence of a string literal: __\n__, __\"__, __\u{263A}__
+fn parse_escape(input: &str) -> ParseResult<'_, char> {
+    preceded(
+        char('\\'),
+        alt((
+            value('\n', char('n')),
+            value('\t', char('t')),
+            value('\r', char('r')),
+            value('\0', char('0')),
+            value('\\', char('\\')),
+            value('"', char('"')),
+            map_opt(
+                delimited(
+                    tag("u{"),
+                    take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()),
+                    char('}'),
+                ),
+                |hex: &str| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+            ),
+        )),
+    )(input)
+}
+
+/// Parses quoted string like: __"some \"text\""__
+fn parse_string(input: &str) -> ParseResult<'_, Literal> {
+    let (input, _) = char('"')(input)?;
+    let chunks = fold_many0(
+        alt((
+            map(is_not("\"\\"), String::from),
+            map(parse_escape, String::from),
+        )),
+        String::new,
+        |mut acc, chunk| {
+            acc.push_str(&chunk);
+            acc
+        },
+    );
+    map(
+        context("closing quote", cut(terminated(chunks, char('"')))),
+        Literal::Str,
+    )(input)
+}
+
+fn parse_literal(input: &str) -> ParseResult<'_, Literal> {
+    alt((
+        parse_number,
+        parse_string,
+        parse_ident,
+        parse_reference,
+        parse_param,
+    ))(input)
 }
 
 /// Operand can be a literal: __12__, __12.23__, __PI__.
 /// or it can be a reference: &Foo, &bar.
 /// or it can be a function: __ema(...)__.
-fn parse_operand(input: &str) -> IResult<&str, Expression> {
+fn parse_operand<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
     let (input, lit) = parse_literal(input)?;
     if matches!(lit, Literal::Ident(_)) && input.chars().peekable().peek() == Some(&'(') {
-        return parse_function(input, lit);
+        return parse_function(input, ops, lit);
     }
     Ok((input, Expression::Const(lit)))
 }
 
-fn parse_parens(input: &str) -> IResult<&str, Expression> {
-    delimited(
-        multispace0,
-        delimited(char('('), parse_expression, char(')')),
-        multispace0,
-    )(input)
+fn parse_parens<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    let expression = |i| parse_expression_with(i, ops);
+    delimited(ws, delimited(char('('), expression, char(')')), ws)(input)
 }
 
-/// Parses either a const/fn operand or expression inside parens
-fn parse_factor(input: &str) -> IResult<&str, Expression> {
-    preceded(multispace0, alt((parse_operand, parse_parens, parse_unary)))(input)
+/// Parses array literal like: __[1, 2, &foo]__
+fn parse_array<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    let expression = |i| parse_expression_with(i, ops);
+    map(
+        delimited(
+            char('['),
+            separated_list0(char(','), expression),
+            context("closing bracket", cut(preceded(ws, char(']')))),
+        ),
+        Expression::Array,
+    )(input)
 }
 
-/// Parses binary expression with exponents: __2**2__
-fn parse_bin_exp(input: &str) -> IResult<&str, Expression> {
-    let (input, lhs) = parse_factor(input)?;
-    fold_many0(
-        preceded(multispace0, preceded(tag("**"), parse_factor)),
-        move || lhs.clone(),
-        |mut acc: Expression, rhs| {
-            acc = Expression::BinOp(Box::new(rhs), Box::new(acc), Operation::Power);
-            acc
-        },
-    )(input)
-    // Ok((input, lhs))
+/// Single access that follows an expression
+enum Postfix {
+    Index(Expression),
+    Field(String),
 }
 
-fn parse_bin_term(input: &str) -> IResult<&str, Expression> {
-    let (input, lhs) = parse_bin_exp(input)?;
-    fold_many0(
+/// Parses element and field accesses that follow an expression:
+/// __weights[0][1]__, __&last.price__
+fn parse_postfix<'i>(
+    input: &'i str,
+    ops: &OperatorTable,
+    base: Expression,
+) -> ParseResult<'i, Expression> {
+    let index = map(
+        delimited(
+            char('['),
+            |i| parse_expression_with(i, ops),
+            context("closing bracket", cut(preceded(ws, char(']')))),
+        ),
+        Postfix::Index,
+    );
+    let field = map(
         preceded(
-            multispace0,
-            pair(alt((char('*'), char('/'))), parse_bin_exp),
+            char('.'),
+            context("field name", cut(take_while1(_is_valid_ident))),
         ),
-        move || lhs.clone(),
-        |mut acc: Expression, (op, rhs)| {
-            let op = if matches!(op, '*') {
-                Operation::Times
-            } else {
-                Operation::Divide
-            };
-            acc = Expression::BinOp(Box::new(acc), Box::new(rhs), op);
-            acc
+        |name: &str| Postfix::Field(name.to_string()),
+    );
+    fold_many0(
+        preceded(ws, alt((index, field))),
+        move || base.clone(),
+        |acc, postfix| match postfix {
+            Postfix::Index(idx) => Expression::Index(Box::new(acc), Box::new(idx)),
+            Postfix::Field(name) => Expression::Field(Box::new(acc), name),
         },
     )(input)
 }
 
-/// Parses any binary arithmetic expression like
+/// Parses an operand, array or expression inside parens along with
+/// indexing and field accesses
+fn parse_primary<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    let (input, base) = alt((
+        |i| parse_operand(i, ops),
+        |i| parse_parens(i, ops),
+        |i| parse_array(i, ops),
+    ))(input)?;
+    parse_postfix(input, ops, base)
+}
+
+/// Parses an operand optionally preceded by prefix operators: __-ema(...)__, __!12__.
+/// The operand of a prefix operator extends over operators binding tighter,
+/// so __-2 ** 2__ is __-(2 ** 2)__.
+fn parse_prefix<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    let (input, _) = ws(input)?;
+    if let Some(op) = ops.find(input, true) {
+        match parse_binary(&input[op.symbol.len()..], ops, op.precedence.into()) {
+            Ok((input, operand)) => return Ok((input, op.apply(vec![operand]))),
+            // a word operator might be an identifier as well
+            Err(nom::Err::Error(_)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    parse_primary(input, ops)
+}
+
+/// Parses operator expressions by climbing precedences of the [OperatorTable].
+/// Operators binding weaker than __min_prec__ are left for the caller.
 /// ```ignore
-/// assert_eq!(parse_binary("1 + 1"), Expression::BinOp(
+/// assert_eq!(parse_binary("1 + 1", &OperatorTable::default(), 0), Expression::BinOp(
 ///                                      Box::new(Expression::Const(
 ///                                          Literal::Number(1.0))),
 ///                                      Box::new(Expression::Const(
 ///                                          Literal::Number(1.0))),
 ///                                      Operation::Plus)
 /// ```
-fn parse_binary(input: &str) -> IResult<&str, Expression> {
-    let (input, lhs) = parse_bin_term(input)?;
-    fold_many0(
-        preceded(
-            multispace0,
-            pair(alt((char('+'), char('-'))), parse_bin_term),
-        ),
-        move || lhs.clone(),
-        |mut acc: Expression, (op, rhs)| {
-            let op = if matches!(op, '+') {
-                Operation::Plus
-            } else {
-                Operation::Minus
-            };
-            acc = Expression::BinOp(Box::new(acc), Box::new(rhs), op);
-            acc
+fn parse_binary<'i>(
+    input: &'i str,
+    ops: &OperatorTable,
+    min_prec: u16,
+) -> ParseResult<'i, Expression> {
+    let (mut input, mut lhs) = parse_prefix(input, ops)?;
+    loop {
+        let (rest, _) = ws(input)?;
+        let op = match ops.find(rest, false) {
+            Some(op) if u16::from(op.precedence) >= min_prec => op,
+            _ => break,
+        };
+        let rest = &rest[op.symbol.len()..];
+        match op.fixity {
+            Fixity::Infix(assoc) => {
+                let prec = u16::from(op.precedence);
+                let next = if assoc == Assoc::Left { prec + 1 } else { prec };
+                match parse_binary(rest, ops, next) {
+                    Ok((rest, rhs)) => {
+                        lhs = op.apply(vec![lhs, rhs]);
+                        input = rest;
+                    }
+                    // the dangling operator is reported by the caller
+                    Err(nom::Err::Error(_)) => break,
+                    Err(err) => return Err(err),
+                }
+            }
+            Fixity::Postfix | Fixity::Prefix => {
+                lhs = op.apply(vec![lhs]);
+                input = rest;
+            }
+        }
+    }
+    Ok((input, lhs))
+}
+
+/// Parses a keyword that is not a part of a longer identifier
+fn keyword<'i>(kw: &'static str) -> impl FnMut(&'i str) -> ParseResult<'i, &'i str> {
+    terminated(tag(kw), not(satisfy(_is_valid_ident)))
+}
+
+/// Parses let-binding like: __let spread = &ask - &bid in spread / 2__
+fn parse_let<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    let (input, _) = keyword("let")(input)?;
+    let expression = |i| parse_expression_with(i, ops);
+    map(
+        cut(tuple((
+            delimited(ws, take_while1(_is_valid_ident), pair(ws, char('='))),
+            expression,
+            preceded(preceded(ws, keyword("in")), expression),
+        ))),
+        |(name, value, body): (&str, Expression, Expression)| Expression::Let {
+            name: name.to_string(),
+            value: Box::new(value),
+            body: Box::new(body),
         },
     )(input)
 }
 
-/// Returns unary expression representation like: __-12__, __-ema(...)__
-fn parse_unary(input: &str) -> IResult<&str, Expression> {
+/// Parses inline function like: __|acc, x| acc + x__
+fn parse_lambda<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    let (input, _) = char('|')(input)?;
     map(
-        pair(alt((char('-'), char('!'))), parse_operand),
-        |(op, rhs)| {
-            Expression::UnOp(
-                if op == '-' {
-                    Operation::Minus
-                } else {
-                    Operation::Factorial
-                },
-                Box::new(rhs),
-            )
+        cut(pair(
+            terminated(
+                separated_list0(char(','), delimited(ws, take_while1(_is_valid_ident), ws)),
+                context("closing bar", preceded(ws, char('|'))),
+            ),
+            |i| parse_expression_with(i, ops),
+        )),
+        |(params, body): (Vec<&str>, Expression)| Expression::Lambda {
+            params: params.into_iter().map(str::to_string).collect(),
+            body: Box::new(body),
         },
     )(input)
 }
 
-fn _parse(input: &str) -> IResult<&str, Expression> {
-    parse_binary(input)
+/// Parses named function argument like: __len=20__
+fn parse_named_arg<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    map(
+        pair(
+            preceded(ws, take_while1(_is_valid_ident)),
+            preceded(delimited(ws, char('='), ws), |i| {
+                parse_expression_with(i, ops)
+            }),
+        ),
+        |(name, value): (&str, Expression)| Expression::NamedArg(name.to_string(), Box::new(value)),
+    )(input)
+}
+
+/// Parses function argument which is either named or positional
+fn parse_argument<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Expression> {
+    alt((
+        |i| parse_named_arg(i, ops),
+        |i| parse_expression_with(i, ops),
+    ))(input)
 }
 
 /// Parses function expressions like `foo(<Expression, *>).*`
-fn parse_function<'a>(input: &str, fn_name: Literal) -> IResult<&str, Expression> {
+fn parse_function<'i>(
+    input: &'i str,
+    ops: &OperatorTable,
+    fn_name: Literal,
+) -> ParseResult<'i, Expression> {
     // let (input, fn_name) = parse_ident(input)?;
     map(
         delimited(
             char('('),
-            preceded(multispace0, separated_list0(char(','), parse_expression)),
-            context("closing paren", cut(preceded(multispace0, char(')')))),
+            preceded(ws, separated_list0(char(','), |i| parse_argument(i, ops))),
+            context("closing paren", cut(preceded(ws, char(')')))),
         ),
         move |result: Vec<Expression>| Expression::Function {
             name: fn_name.clone(),
@@ -170,14 +372,175 @@ fn parse_function<'a>(input: &str, fn_name: Literal) -> IResult<&str, Expression
 }
 
 /// Parses function expressions like
-/// EXPRESSION := FUNCTION | CONST | BINARY
-pub fn parse_expression(input: &str) -> IResult<&str, Expression> {
-    preceded(multispace0, _parse)(input)
+/// EXPRESSION := LET | LAMBDA | FUNCTION | CONST | BINARY
+pub fn parse_expression(input: &str) -> ParseResult<'_, Expression> {
+    parse_expression_with(input, &OperatorTable::default())
+}
+
+/// Parses an expression with extra operators of the table
+pub fn parse_expression_with<'i>(
+    input: &'i str,
+    ops: &OperatorTable,
+) -> ParseResult<'i, Expression> {
+    delimited(
+        ws,
+        alt((
+            |i| parse_let(i, ops),
+            |i| parse_lambda(i, ops),
+            |i| parse_binary(i, ops, 0),
+        )),
+        ws,
+    )(input)
+}
+
+/// Parses function definition like: __def zscore(x, m, s) = (x - m) / s__
+pub fn parse_definition<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Definition> {
+    let (input, _) = preceded(ws, keyword("def"))(input)?;
+    map(
+        cut(tuple((
+            preceded(ws, take_while1(_is_valid_ident)),
+            delimited(
+                preceded(ws, char('(')),
+                separated_list0(char(','), delimited(ws, take_while1(_is_valid_ident), ws)),
+                char(')'),
+            ),
+            preceded(delimited(ws, char('='), ws), |i| {
+                parse_expression_with(i, ops)
+            }),
+        ))),
+        |(name, params, body): (&str, Vec<&str>, Expression)| Definition::Function {
+            name: name.to_string(),
+            params: params.into_iter().map(String::from).collect(),
+            body,
+        },
+    )(input)
+}
+
+/// Parses constant definition like: __const K = 2 * PI__
+pub fn parse_const_definition<'i>(
+    input: &'i str,
+    ops: &OperatorTable,
+) -> ParseResult<'i, Definition> {
+    let (input, _) = preceded(ws, keyword("const"))(input)?;
+    map(
+        cut(pair(
+            preceded(ws, take_while1(_is_valid_ident)),
+            preceded(delimited(ws, char('='), ws), |i| {
+                parse_expression_with(i, ops)
+            }),
+        )),
+        |(name, body): (&str, Expression)| Definition::Const {
+            name: name.to_string(),
+            body,
+        },
+    )(input)
+}
+
+/// Parses a script of definitions optionally separated by semicolons
+pub fn parse_script<'i>(input: &'i str, ops: &OperatorTable) -> ParseResult<'i, Vec<Definition>> {
+    terminated(
+        many0(terminated(
+            alt((
+                |i| parse_definition(i, ops),
+                |i| parse_const_definition(i, ops),
+            )),
+            opt(preceded(ws, char(';'))),
+        )),
+        ws,
+    )(input)
+}
+
+/// Parses the whole formula. Unlike [parse_expression] it fails
+/// if some input is left unparsed.
+pub fn parse_formula(input: &str) -> Result<Expression, SyntaxError> {
+    parse_formula_with(input, &OperatorTable::default())
+}
+
+/// Parses the whole formula with extra operators of the table
+pub fn parse_formula_with(
+    input: &str,
+    operators: &OperatorTable,
+) -> Result<Expression, SyntaxError> {
+    parse_complete(input, operators, |i| parse_expression_with(i, operators))
+}
+
+/// Parses the whole script of definitions
+pub fn parse_definitions(input: &str) -> Result<Vec<Definition>, SyntaxError> {
+    parse_definitions_with(input, &OperatorTable::default())
+}
+
+/// Parses the whole script of definitions with extra operators of the table
+pub fn parse_definitions_with(
+    input: &str,
+    operators: &OperatorTable,
+) -> Result<Vec<Definition>, SyntaxError> {
+    parse_complete(input, operators, |i| parse_script(i, operators))
+}
+
+fn parse_complete<'i, T>(
+    input: &'i str,
+    operators: &OperatorTable,
+    mut parser: impl FnMut(&'i str) -> ParseResult<'i, T>,
+) -> Result<T, SyntaxError> {
+    match parser(input) {
+        Ok(("", parsed)) => Ok(parsed),
+        Ok((rest, _)) => {
+            let token = rest.split_whitespace().next().unwrap_or(rest);
+            let token: String = token.chars().take(16).collect();
+            Err(
+                SyntaxError::at(input, rest, format!("unexpected input '{}'", token))
+                    .with_hint(hint_for(rest, operators)),
+            )
+        }
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            let (rest, kind) = &e.errors[0];
+            // the innermost context tells what was expected
+            let expected = e.errors.iter().find_map(|(_, kind)| match kind {
+                VerboseErrorKind::Context(ctx) => Some(ctx.to_string()),
+                _ => None,
+            });
+            let message = match (expected, kind) {
+                (Some(ctx), _) => format!("expected {}", ctx),
+                (None, VerboseErrorKind::Char(c)) => format!("expected '{}'", c),
+                (None, _) if rest.is_empty() => "unexpected end of input".to_string(),
+                (None, _) => "invalid syntax".to_string(),
+            };
+            Err(SyntaxError::at(input, rest, message))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(SyntaxError::at(input, "", "unexpected end of input")),
+    }
+}
+
+/// Guesses what was meant by the input left unparsed
+fn hint_for(rest: &str, operators: &OperatorTable) -> Option<String> {
+    let c = rest.chars().next()?;
+    if let Some(op) = operators.find(rest, false) {
+        return Some(format!(
+            "did you mean to put an operand after '{}'?",
+            op.symbol
+        ));
+    }
+    let hint = match c {
+        ')' | ']' => format!("did you mean to remove the unmatched '{}'?", c),
+        '=' => "did you mean a let-binding: let name = value in ...?".to_string(),
+        ',' => "did you mean an array of values: [a, b]?".to_string(),
+        c if _is_valid_ident(c) || matches!(c, '&' | '$' | '(' | '[' | '"') => {
+            "did you mean to join the expressions with an operator like '+'?".to_string()
+        }
+        _ => return None,
+    };
+    Some(hint)
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
+    use crate::operators::{OpKind, Operator};
+
+    fn operand(input: &str) -> ParseResult<'_, Expression> {
+        parse_operand(input, &OperatorTable::default())
+    }
 
     macro_rules! test_op {
         ($parser:expr, $str:expr => $tok:expr) => {
@@ -196,6 +559,34 @@ mod tests {
     fn test_id() {
         test_op!(parse_ident, "hello" => Literal::Ident("hello".to_string()));
         test_op!(parse_ident, "hello world" => Literal::Ident("hello".to_string()));
+        test_op!(parse_ident, "math::PI" => Literal::Ident("math::PI".to_string()));
+        test_op!(parse_expression, "ta::ema(&px, 10) * 2" => Expression::BinOp(
+            Box::new(Expression::Function {
+                name: Literal::Ident("ta::ema".to_string()),
+                args: vec![
+                    Expression::Const(Literal::Ref("px".to_string())),
+                    Expression::Const(Literal::Number(10.0)),
+                ],
+            }),
+            Box::new(Expression::Const(Literal::Number(2.0))),
+            Operation::Times));
+        assert_eq!(
+            parse_formula("ta:: ema(1)").unwrap_err().to_string(),
+            "expected name after '::' at line 1, column 5"
+        );
+    }
+
+    #[test]
+    fn test_str() {
+        test_op!(parse_string, r#""""# => Literal::Str(String::new()));
+        test_op!(parse_string, r#""hello world""# => Literal::Str("hello world".to_string()));
+        test_op!(parse_string, r#""a\"b\\c\n\u{263A}""# => Literal::Str("a\"b\\c\n\u{263A}".to_string()));
+        test_op!(parse_expression, r#"foo("x,y)")"# => Expression::Function {
+            name: Literal::Ident("foo".to_string()),
+            args: vec![Expression::Const(Literal::Str("x,y)".to_string()))]
+        });
+        assert!(parse_string(r#""unterminated"#).is_err());
+        assert!(parse_string(r#""bad \q escape""#).is_err());
     }
 
     #[test]
@@ -203,19 +594,33 @@ mod tests {
         test_op!(parse_number, "12" => Literal::Number(12.0f64));
         test_op!(parse_number, "22.22" => Literal::Number(22.22f64));
         test_op!(parse_number, "1e-10" => Literal::Number(1e-10f64));
+        test_op!(parse_number, "500ms" => Literal::Duration(500.0, TimeUnit::Milliseconds));
+        test_op!(parse_number, "1.5h" => Literal::Duration(1.5, TimeUnit::Hours));
+        test_op!(parse_number, "20us" => Literal::Duration(20.0, TimeUnit::Microseconds));
+        test_op!(parse_number, "20µs" => Literal::Duration(20.0, TimeUnit::Microseconds));
+        test_op!(parse_number, "100ns" => Literal::Duration(100.0, TimeUnit::Nanoseconds));
+        test_op!(parse_expression, "ma(&px, 5m)" => Expression::Function {
+            name: Literal::Ident("ma".to_string()),
+            args: vec![
+                Expression::Const(Literal::Ref("px".to_string())),
+                Expression::Const(Literal::Duration(5.0, TimeUnit::Minutes)),
+            ],
+        });
+        // unknown suffix is not a unit
+        assert_eq!(parse_number("5min"), Ok(("min", Literal::Number(5.0))));
     }
 
     #[test]
     fn test_fn() {
-        test_op!(parse_operand, "foo()" => Expression::Function { name: Literal::Ident("foo".to_string()), args: vec![] });
-        test_op!(parse_operand, "foo(bar(), baz())" => Expression::Function {
+        test_op!(operand, "foo()" => Expression::Function { name: Literal::Ident("foo".to_string()), args: vec![] });
+        test_op!(operand, "foo(bar(), baz())" => Expression::Function {
         name: Literal::Ident("foo".to_string()),
         args: vec![
             Expression::Function { name: Literal::Ident("bar".to_string()), args: vec![] },
             Expression::Function { name: Literal::Ident("baz".to_string()), args: vec![] },
         ]});
 
-        test_op!(parse_operand, "foo(22, 2 + 2)" => Expression::Function {
+        test_op!(operand, "foo(22, 2 + 2)" => Expression::Function {
         name: Literal::Ident("foo".to_string()),
         args: vec![
             Expression::Const(Literal::Number(22.0)) ,
@@ -225,6 +630,17 @@ mod tests {
         ]});
     }
 
+    #[test]
+    fn test_named_args() {
+        test_op!(operand, "jma(&px, len=20, phase = -1)" => Expression::Function {
+        name: Literal::Ident("jma".to_string()),
+        args: vec![
+            Expression::Const(Literal::Ref("px".to_string())),
+            Expression::NamedArg("len".to_string(), Box::new(Expression::Const(Literal::Number(20.0)))),
+            Expression::NamedArg("phase".to_string(), Box::new(Expression::Const(Literal::Number(-1.0)))),
+        ]});
+    }
+
     #[test]
     fn test_unary() {
         test_op!(parse_expression, "-12" =>
@@ -246,30 +662,158 @@ mod tests {
 
     #[test]
     fn test_bin_exp() {
-        test_op!(parse_bin_exp, "2 ** 3" => Expression::BinOp(
-            Box::new(Expression::Const(Literal::Number(3.0))),
-            Box::new(Expression::Const(Literal::Number(2.0))),
+        let num = |n: f64| Box::new(Expression::Const(Literal::Number(n)));
+        test_op!(parse_expression, "2 ** 3" => Expression::BinOp(num(2.0), num(3.0), Operation::Power));
+        // exponents are right associative
+        test_op!(parse_expression, "2 ** 3 ** 4" => Expression::BinOp(
+            num(2.0),
+            Box::new(Expression::BinOp(num(3.0), num(4.0), Operation::Power)),
             Operation::Power));
+        test_op!(parse_expression, "-2 ** 2" => Expression::UnOp(
+            Operation::Minus,
+            Box::new(Expression::BinOp(num(2.0), num(2.0), Operation::Power))));
+        test_op!(parse_expression, "2 ** -1" => Expression::BinOp(num(2.0), num(-1.0), Operation::Power));
+        test_op!(parse_expression, "2 * 3 ** 2" => Expression::BinOp(
+            num(2.0),
+            Box::new(Expression::BinOp(num(3.0), num(2.0), Operation::Power)),
+            Operation::Times));
+    }
 
-        test_op!(parse_bin_exp, "2 ** 3 ** 4" => Expression::BinOp(
-            Box::new(Expression::Const(Literal::Number(4.0))),
-            Box::new(Expression::BinOp(
-                Box::new(Expression::Const(Literal::Number(3.0))),
-                Box::new(Expression::Const(Literal::Number(2.0))),
-                Operation::Power)),
-            Operation::Power));
+    #[test]
+    fn test_precedence() {
+        let num = |n: f64| Box::new(Expression::Const(Literal::Number(n)));
+        let ident = |name: &str| Box::new(Expression::Const(Literal::Ident(name.to_string())));
+        test_op!(parse_expression, "-(a + b)" => Expression::UnOp(
+            Operation::Minus,
+            Box::new(Expression::BinOp(ident("a"), ident("b"), Operation::Plus))));
+        test_op!(parse_expression, "8 - 4 - 2" => Expression::BinOp(
+            Box::new(Expression::BinOp(num(8.0), num(4.0), Operation::Minus)),
+            num(2.0),
+            Operation::Minus));
+        test_op!(parse_expression, "-2 * 3" => Expression::BinOp(num(-2.0), num(3.0), Operation::Times));
+        test_op!(parse_expression, "1 - -1" => Expression::BinOp(num(1.0), num(-1.0), Operation::Minus));
+        test_op!(parse_expression, "--1" => Expression::Const(Literal::Number(1.0)));
+        assert!(parse_number("-1").is_err());
+    }
+
+    #[test]
+    fn test_custom_operators() {
+        let ident = |name: &str| Expression::Const(Literal::Ident(name.to_string()));
+        let call = |name: &str, args| Expression::Function {
+            name: Literal::Ident(name.to_string()),
+            args,
+        };
+        let mut table = OperatorTable::default();
+        table
+            .add(Operator::new(
+                "%%",
+                Fixity::Infix(Assoc::Left),
+                20,
+                OpKind::Call("rem".to_string()),
+            ))
+            .unwrap();
+        table
+            .add(Operator::new(
+                "mod",
+                Fixity::Infix(Assoc::Left),
+                20,
+                OpKind::Call("rem".to_string()),
+            ))
+            .unwrap();
+        table
+            .add(Operator::new(
+                "?",
+                Fixity::Postfix,
+                50,
+                OpKind::Call("valid".to_string()),
+            ))
+            .unwrap();
+        table
+            .add(Operator::new(
+                "==",
+                Fixity::Infix(Assoc::Left),
+                5,
+                OpKind::Call("eq".to_string()),
+            ))
+            .unwrap();
+        assert_eq!(
+            parse_formula_with("a + b %% c", &table),
+            Ok(Expression::BinOp(
+                Box::new(ident("a")),
+                Box::new(call("rem", vec![ident("b"), ident("c")])),
+                Operation::Plus
+            ))
+        );
+        assert_eq!(
+            parse_formula_with("a mod b == modulo?", &table),
+            Ok(call(
+                "eq",
+                vec![
+                    call("rem", vec![ident("a"), ident("b")]),
+                    call("valid", vec![ident("modulo")])
+                ]
+            ))
+        );
+        // named arguments are not confused with comparison
+        assert_eq!(
+            parse_formula_with("f(len=a == b)", &table),
+            Ok(call(
+                "f",
+                vec![Expression::NamedArg(
+                    "len".to_string(),
+                    Box::new(call("eq", vec![ident("a"), ident("b")]))
+                )]
+            ))
+        );
+        // the table is not used by default
+        assert!(parse_formula("a %% b").is_err());
+        assert_eq!(
+            parse_formula_with("a %%", &table).unwrap_err().to_string(),
+            "unexpected input '%%' at line 1, column 3 (did you mean to put an operand after '%%'?)"
+        );
+
+        let conflict = |symbol: &str, fixity| {
+            OperatorTable::default()
+                .add(Operator::new(
+                    symbol,
+                    fixity,
+                    1,
+                    OpKind::Call("f".to_string()),
+                ))
+                .unwrap_err()
+        };
+        assert_eq!(
+            conflict("+", Fixity::Infix(Assoc::Left)),
+            "Operator '+' is already defined"
+        );
+        assert_eq!(
+            conflict("//", Fixity::Infix(Assoc::Left)),
+            "Operator '//' conflicts with the syntax"
+        );
+        assert_eq!(
+            conflict("&", Fixity::Prefix),
+            "Operator '&' conflicts with the syntax"
+        );
+        assert!(OperatorTable::default()
+            .add(Operator::new(
+                "+",
+                Fixity::Prefix,
+                30,
+                OpKind::Builtin(Operation::Plus)
+            ))
+            .is_ok());
     }
 
     #[test]
     fn test_bin() {
-        test_op!(parse_binary, "12 + 12" =>  Expression::BinOp(
+        test_op!(parse_expression, "12 + 12" =>  Expression::BinOp(
                                               Box::new(Expression::Const(
                                                   Literal::Number(12.0))),
                                               Box::new(Expression::Const(
                                                   Literal::Number(12.0))),
                                               Operation::Plus)
         );
-        test_op!(parse_binary, "foo() + 12" =>  Expression::BinOp(
+        test_op!(parse_expression, "foo() + 12" =>  Expression::BinOp(
                                             Box::new(Expression::Function
                                                      { name: Literal::Ident("foo".to_string()),
                                                        args: vec![] }),
@@ -278,7 +822,7 @@ mod tests {
                                               Operation::Plus)
         );
 
-        test_op!(parse_binary, "12 + foo()" =>  Expression::BinOp(
+        test_op!(parse_expression, "12 + foo()" =>  Expression::BinOp(
                                             Box::new(Expression::Const(
                                                   Literal::Number(12.0))),
                                             Box::new(Expression::Function
@@ -347,6 +891,210 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_param() {
+        test_op!(
+            parse_expression,
+            "$threshold * 2" => Expression::BinOp(
+                Box::new(Expression::Const(Literal::Param("threshold".to_string()))),
+                Box::new(Expression::Const(Literal::Number(2.0))),
+                Operation::Times)
+        );
+        let err = parse_formula("1 + $").unwrap_err();
+        assert_eq!(err.offset, 5);
+        assert!(
+            err.to_string().contains("parameter name after '$'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_let() {
+        test_op!(parse_expression, "let x = &a - 1 in x / (x + 1)" => Expression::Let {
+            name: "x".to_string(),
+            value: Box::new(Expression::BinOp(
+                Box::new(Expression::Const(Literal::Ref("a".to_string()))),
+                Box::new(Expression::Const(Literal::Number(1.0))),
+                Operation::Minus)),
+            body: Box::new(Expression::BinOp(
+                Box::new(Expression::Const(Literal::Ident("x".to_string()))),
+                Box::new(Expression::BinOp(
+                    Box::new(Expression::Const(Literal::Ident("x".to_string()))),
+                    Box::new(Expression::Const(Literal::Number(1.0))),
+                    Operation::Plus)),
+                Operation::Divide)),
+        });
+        test_op!(parse_expression, "let a = 1 in let b = 2 in a" => Expression::Let {
+            name: "a".to_string(),
+            value: Box::new(Expression::Const(Literal::Number(1.0))),
+            body: Box::new(Expression::Let {
+                name: "b".to_string(),
+                value: Box::new(Expression::Const(Literal::Number(2.0))),
+                body: Box::new(Expression::Const(Literal::Ident("a".to_string()))),
+            }),
+        });
+        test_op!(parse_expression, "letter" => Expression::Const(Literal::Ident("letter".to_string())));
+        assert!(parse_expression("let x = 1 inx").is_err());
+    }
+
+    #[test]
+    fn test_lambda() {
+        let ident = |name: &str| Box::new(Expression::Const(Literal::Ident(name.to_string())));
+        test_op!(parse_expression, "reduce(0, |acc, x| acc + x, &px)" => Expression::Function {
+            name: Literal::Ident("reduce".to_string()),
+            args: vec![
+                Expression::Const(Literal::Number(0.0)),
+                Expression::Lambda {
+                    params: vec!["acc".to_string(), "x".to_string()],
+                    body: Box::new(Expression::BinOp(ident("acc"), ident("x"), Operation::Plus)),
+                },
+                Expression::Const(Literal::Ref("px".to_string())),
+            ],
+        });
+        test_op!(parse_expression, "|| 1" => Expression::Lambda {
+            params: vec![],
+            body: Box::new(Expression::Const(Literal::Number(1.0))),
+        });
+        assert!(parse_expression("|a, b 1").is_err());
+        assert!(parse_expression("|a|").is_err());
+    }
+
+    #[test]
+    fn test_definition() {
+        let (rest, defs) = parse_script(
+            "def one() = 1;\n def zscore(x, m, s) = (x - m) / s\n",
+            &OperatorTable::default(),
+        )
+        .unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            defs[0],
+            Definition::Function {
+                name: "one".to_string(),
+                params: vec![],
+                body: Expression::Const(Literal::Number(1.0)),
+            }
+        );
+        assert_eq!(defs[1].name(), "zscore");
+        assert!(
+            matches!(&defs[1], Definition::Function { params, .. } if params == &["x", "m", "s"])
+        );
+        assert!(parse_definition("def f(x) x", &OperatorTable::default()).is_err());
+
+        let (rest, defs) = parse_script(
+            "const K = 2 * PI; def f(x) = x * K",
+            &OperatorTable::default(),
+        )
+        .unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            defs[0],
+            Definition::Const {
+                name: "K".to_string(),
+                body: parse_formula("2 * PI").unwrap(),
+            }
+        );
+        assert!(matches!(defs[1], Definition::Function { .. }));
+        assert!(parse_const_definition("const K", &OperatorTable::default()).is_err());
+        assert!(parse_const_definition("constant(1)", &OperatorTable::default()).is_err());
+    }
+
+    #[test]
+    fn test_array() {
+        let num = |n: f64| Expression::Const(Literal::Number(n));
+        test_op!(parse_expression, "[]" => Expression::Array(vec![]));
+        test_op!(parse_expression, "[1, 2 + 3]" => Expression::Array(vec![
+            num(1.0),
+            Expression::BinOp(Box::new(num(2.0)), Box::new(num(3.0)), Operation::Plus),
+        ]));
+        test_op!(parse_expression, "w[0] * [1, 2][1 + 0]" => Expression::BinOp(
+            Box::new(Expression::Index(
+                Box::new(Expression::Const(Literal::Ident("w".to_string()))),
+                Box::new(num(0.0)))),
+            Box::new(Expression::Index(
+                Box::new(Expression::Array(vec![num(1.0), num(2.0)])),
+                Box::new(Expression::BinOp(Box::new(num(1.0)), Box::new(num(0.0)), Operation::Plus)))),
+            Operation::Times));
+        test_op!(parse_expression, "-w[1]" => Expression::UnOp(
+            Operation::Minus,
+            Box::new(Expression::Index(
+                Box::new(Expression::Const(Literal::Ident("w".to_string()))),
+                Box::new(num(1.0))))));
+        assert!(parse_expression("[1, 2").is_err());
+    }
+
+    #[test]
+    fn test_field() {
+        test_op!(parse_expression, "&last.price" => Expression::Field(
+            Box::new(Expression::Const(Literal::Ref("last".to_string()))),
+            "price".to_string()));
+        test_op!(parse_expression, "&ticks[0].time - 1.5" => Expression::BinOp(
+            Box::new(Expression::Field(
+                Box::new(Expression::Index(
+                    Box::new(Expression::Const(Literal::Ref("ticks".to_string()))),
+                    Box::new(Expression::Const(Literal::Number(0.0))))),
+                "time".to_string())),
+            Box::new(Expression::Const(Literal::Number(1.5))),
+            Operation::Minus));
+        assert!(parse_expression("&last.").is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        let num = |n: f64| Box::new(Expression::Const(Literal::Number(n)));
+        let expr = "# spread in ticks\n  1 + // half\n 2 /* inline */ * 3 # tail";
+        assert_eq!(
+            parse_expression(expr),
+            Ok((
+                "",
+                Expression::BinOp(
+                    num(1.0),
+                    Box::new(Expression::BinOp(num(2.0), num(3.0), Operation::Times)),
+                    Operation::Plus
+                )
+            ))
+        );
+        test_op!(parse_expression, "max(1, /* b */ 2)" => Expression::Function {
+            name: Literal::Ident("max".to_string()),
+            args: vec![*num(1.0), *num(2.0)],
+        });
+        test_op!(parse_expression, "4 / 2" => Expression::BinOp(num(4.0), num(2.0), Operation::Divide));
+        // the rest is left with its original offset
+        let expr = "1 /* a */ )";
+        assert_eq!(parse_expression(expr).unwrap().0, &expr[10..]);
+        assert!(parse_expression("1 /* open").is_err());
+        let (rest, defs) = parse_script(
+            "# helpers\ndef id(x) = x // identity\n/* end */",
+            &OperatorTable::default(),
+        )
+        .unwrap();
+        assert_eq!((rest, defs.len()), ("", 1));
+    }
+
+    #[test]
+    fn test_complete_input() {
+        let err = |input: &str| parse_formula(input).unwrap_err().to_string();
+        assert!(parse_formula(" 1 + 2 # sum").is_ok());
+        assert_eq!(
+            err("2 + 2 )foo"),
+            "unexpected input ')foo' at line 1, column 7 (did you mean to remove the unmatched ')'?)"
+        );
+        assert_eq!(
+            err("ema(&x, 3)\n  ema"),
+            "unexpected input 'ema' at line 2, column 3 (did you mean to join the expressions with an operator like '+'?)"
+        );
+        assert_eq!(
+            err("max(1, 2"),
+            "expected closing paren at line 1, column 9"
+        );
+        assert_eq!(err("\"abc"), "expected closing quote at line 1, column 5");
+        let e = parse_formula("1 +").unwrap_err();
+        assert_eq!((e.offset, e.line, e.column), (2, 1, 3));
+        assert!(parse_definitions("def f(x) = x; def g(y) = y").is_ok());
+        assert_eq!(parse_definitions("def f(x) = x )").unwrap_err().offset, 13);
+    }
+
     #[test]
     fn test_const() {
         test_op!(parse_expression, "12" => Expression::Const(Literal::Number(12.0)));
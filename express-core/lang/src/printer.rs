@@ -0,0 +1,380 @@
+use crate::ast::{Definition, Expression, Literal};
+use crate::error::SyntaxError;
+use crate::operators::{Assoc, Fixity, OpKind, Operator, OperatorTable};
+use crate::parser::{parse_definitions_with, parse_formula_with};
+use std::fmt::{Display, Write};
+
+/// Precedence of operands that never need parens
+const ATOM: u16 = u16::MAX;
+
+/// Prints expression in the canonical form: single spaces around
+/// infix operators, `, ` between arguments and only the parens
+/// required by precedence. The result parses back to an equal expression.
+/// ```ignore
+/// assert_eq!(print_expression(&parse_formula("(1+ (2*3))").unwrap(), &table), "1 + 2 * 3");
+/// ```
+pub fn print_expression(expr: &Expression, operators: &OperatorTable) -> String {
+    let mut out = String::new();
+    Printer { operators }.expr(&mut out, expr, 0);
+    out
+}
+
+/// Prints definition like: __def zscore(x, m, s) = (x - m) / s__
+/// or __const K = 2 * PI__
+pub fn print_definition(definition: &Definition, operators: &OperatorTable) -> String {
+    match definition {
+        Definition::Function { name, params, body } => format!(
+            "def {}({}) = {}",
+            name,
+            params.join(", "),
+            print_expression(body, operators)
+        ),
+        Definition::Const { name, body } => {
+            format!("const {} = {}", name, print_expression(body, operators))
+        }
+    }
+}
+
+/// Reformats the formula into its canonical form. Comments are dropped.
+pub fn format_formula(input: &str, operators: &OperatorTable) -> Result<String, SyntaxError> {
+    Ok(print_expression(
+        &parse_formula_with(input, operators)?,
+        operators,
+    ))
+}
+
+/// Reformats the script of definitions, one definition per line.
+/// Comments are dropped.
+pub fn format_definitions(input: &str, operators: &OperatorTable) -> Result<String, SyntaxError> {
+    let definitions = parse_definitions_with(input, operators)?;
+    Ok(definitions
+        .iter()
+        .map(|definition| print_definition(definition, operators) + "\n")
+        .collect())
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", print_expression(self, &OperatorTable::default()))
+    }
+}
+
+impl Display for Definition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", print_definition(self, &OperatorTable::default()))
+    }
+}
+
+struct Printer<'t> {
+    operators: &'t OperatorTable,
+}
+
+impl Printer<'_> {
+    /// Finds an operator the expression was parsed from
+    fn operator_of(&self, expr: &Expression) -> Option<&Operator> {
+        let (kind, operands) = match expr {
+            Expression::BinOp(_, _, op) => (OpKind::Builtin(*op), 2),
+            Expression::UnOp(op, _) => (OpKind::Builtin(*op), 1),
+            Expression::Function {
+                name: Literal::Ident(name),
+                args,
+            } if !args
+                .iter()
+                .any(|arg| matches!(arg, Expression::NamedArg(..))) =>
+            {
+                (OpKind::Call(name.clone()), args.len())
+            }
+            _ => return None,
+        };
+        self.operators.operators().iter().find(|op| {
+            op.kind == kind
+                && match op.fixity {
+                    Fixity::Prefix | Fixity::Postfix => operands == 1,
+                    Fixity::Infix(_) => operands == 2,
+                }
+        })
+    }
+
+    /// Returns how tight the printed expression binds
+    fn precedence(&self, expr: &Expression) -> u16 {
+        match expr {
+            Expression::Const(Literal::Number(num)) if num.is_sign_negative() => self
+                .operators
+                .find("-", true)
+                .map_or(ATOM, |op| op.precedence.into()),
+            Expression::Let { .. } | Expression::Lambda { .. } => 0,
+            _ => self
+                .operator_of(expr)
+                .map_or(ATOM, |op| op.precedence.into()),
+        }
+    }
+
+    fn expr(&self, out: &mut String, expr: &Expression, min_prec: u16) {
+        if self.precedence(expr) < min_prec {
+            out.push('(');
+            self.expr(out, expr, 0);
+            out.push(')');
+            return;
+        }
+        if let Some(op) = self.operator_of(expr) {
+            return self.operation(out, op, expr);
+        }
+        match expr {
+            Expression::Const(lit) => literal(out, lit),
+            Expression::Function { name, args } => {
+                literal(out, name);
+                self.list(out, "(", args, ")");
+            }
+            Expression::BinOp(lhs, rhs, op) => {
+                self.expr(out, lhs, ATOM);
+                write!(out, " {} ", op).unwrap();
+                self.expr(out, rhs, ATOM);
+            }
+            Expression::UnOp(op, rhs) => {
+                write!(out, "{}", op).unwrap();
+                self.expr(out, rhs, ATOM);
+            }
+            Expression::Array(items) => self.list(out, "[", items, "]"),
+            Expression::Index(base, index) => {
+                self.postfix_base(out, base);
+                out.push('[');
+                self.expr(out, index, 0);
+                out.push(']');
+            }
+            Expression::Field(base, name) => {
+                self.postfix_base(out, base);
+                write!(out, ".{}", name).unwrap();
+            }
+            Expression::NamedArg(name, value) => {
+                write!(out, "{}=", name).unwrap();
+                self.expr(out, value, 0);
+            }
+            Expression::Let { name, value, body } => {
+                write!(out, "let {} = ", name).unwrap();
+                self.expr(out, value, 0);
+                out.push_str(" in ");
+                self.expr(out, body, 0);
+            }
+            Expression::Lambda { params, body } => {
+                write!(out, "|{}| ", params.join(", ")).unwrap();
+                self.expr(out, body, 0);
+            }
+        }
+    }
+
+    /// Prints operands around the operator
+    fn operation(&self, out: &mut String, op: &Operator, expr: &Expression) {
+        let operands: Vec<&Expression> = match expr {
+            Expression::BinOp(lhs, rhs, _) => vec![lhs, rhs],
+            Expression::UnOp(_, rhs) => vec![rhs],
+            Expression::Function { args, .. } => args.iter().collect(),
+            _ => unreachable!(),
+        };
+        let prec = u16::from(op.precedence);
+        // word operators are kept apart from their operands
+        let space = if op.is_word() { " " } else { "" };
+        match op.fixity {
+            Fixity::Prefix => {
+                write!(out, "{}{}", op.symbol, space).unwrap();
+                self.expr(out, operands[0], prec);
+            }
+            Fixity::Postfix => {
+                self.expr(out, operands[0], prec);
+                write!(out, "{}{}", space, op.symbol).unwrap();
+            }
+            Fixity::Infix(assoc) => {
+                let (lhs, rhs) = match assoc {
+                    Assoc::Left => (prec, prec + 1),
+                    Assoc::Right => (prec + 1, prec),
+                };
+                self.expr(out, operands[0], lhs);
+                write!(out, " {} ", op.symbol).unwrap();
+                self.expr(out, operands[1], rhs);
+            }
+        }
+    }
+
+    /// Prints an indexed value. Numbers are put in parens, so that
+    /// __(1).x__ is not read as __1.__ followed by __x__
+    fn postfix_base(&self, out: &mut String, base: &Expression) {
+        match base {
+            Expression::Const(Literal::Number(_)) => {
+                out.push('(');
+                self.expr(out, base, 0);
+                out.push(')');
+            }
+            _ => self.expr(out, base, ATOM),
+        }
+    }
+
+    fn list(&self, out: &mut String, open: &str, items: &[Expression], close: &str) {
+        out.push_str(open);
+        for (pos, item) in items.iter().enumerate() {
+            if pos > 0 {
+                out.push_str(", ");
+            }
+            self.expr(out, item, 0);
+        }
+        out.push_str(close);
+    }
+}
+
+fn literal(out: &mut String, lit: &Literal) {
+    match lit {
+        Literal::Ident(name) => out.push_str(name),
+        Literal::Number(num) => write!(out, "{}", num).unwrap(),
+        Literal::Ref(name) => write!(out, "&{}", name).unwrap(),
+        Literal::Param(name) => write!(out, "${}", name).unwrap(),
+        Literal::Duration(span, unit) => write!(out, "{}{}", span, unit).unwrap(),
+        Literal::Str(string) => {
+            out.push('"');
+            for c in string.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    '\r' => out.push_str("\\r"),
+                    '\0' => out.push_str("\\0"),
+                    c if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32).unwrap(),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Operation;
+    use crate::parser::parse_formula;
+
+    /// Checks that the formula is printed as expected and parses back
+    fn assert_prints(table: &OperatorTable, input: &str, expected: &str) {
+        let expr = parse_formula_with(input, table).unwrap();
+        let printed = print_expression(&expr, table);
+        assert_eq!(printed, expected);
+        assert_eq!(parse_formula_with(&printed, table), Ok(expr));
+    }
+
+    #[test]
+    fn test_minimal_parens() {
+        let table = OperatorTable::default();
+        assert_prints(&table, "(1+(2*3))", "1 + 2 * 3");
+        assert_prints(&table, "(1 + 2) * 3", "(1 + 2) * 3");
+        assert_prints(&table, "8 - (4 - 2) - 1", "8 - (4 - 2) - 1");
+        assert_prints(&table, "(8 - 4) - 2", "8 - 4 - 2");
+        assert_prints(&table, "2 ** (3 ** 2)", "2 ** 3 ** 2");
+        assert_prints(&table, "(2 ** 3) ** 2", "(2 ** 3) ** 2");
+        assert_prints(&table, "-(a + b)", "-(a + b)");
+        assert_prints(&table, "-(2 ** 2)", "-2 ** 2");
+        assert_prints(&table, "(-2) ** 2", "(-2) ** 2");
+        assert_prints(&table, "(-a) ** 2", "(-a) ** 2");
+        assert_prints(&table, "1 - (-1)", "1 - -1");
+        assert_prints(&table, "!(-ema(&px))", "!-ema(&px)");
+        assert_prints(&table, "(a * b)[0].price", "(a * b)[0].price");
+        assert_prints(&table, "(1).x", "(1).x");
+    }
+
+    #[test]
+    fn test_print_constructs() {
+        let table = OperatorTable::default();
+        assert_prints(
+            &table,
+            "jma( &px,len = 20,phase=-1 ) # smooth",
+            "jma(&px, len=20, phase=-1)",
+        );
+        assert_prints(&table, "[ 1,2 ][ 0 ]", "[1, 2][0]");
+        assert_prints(&table, "(&px*$k)", "&px * $k");
+        assert_prints(&table, "ma(&px,5m) / 1.5h", "ma(&px, 5m) / 1.5h");
+        assert_prints(
+            &table,
+            r#"concat("a \"b\"\n", "\u{1}")"#,
+            r#"concat("a \"b\"\n", "\u{1}")"#,
+        );
+        assert_prints(
+            &table,
+            "let x = (&a - 1) in (x / (x + 1))",
+            "let x = &a - 1 in x / (x + 1)",
+        );
+        assert_prints(
+            &table,
+            "reduce(0, |acc,x| (acc + x), &px)",
+            "reduce(0, |acc, x| acc + x, &px)",
+        );
+        assert_prints(
+            &table,
+            "1 + (let x = 1 in x) * (|| 1)",
+            "1 + (let x = 1 in x) * (|| 1)",
+        );
+        assert_eq!(parse_formula("(1+2)*3").unwrap().to_string(), "(1 + 2) * 3");
+    }
+
+    #[test]
+    fn test_print_custom_operators() {
+        let mut table = OperatorTable::default();
+        let call = |name: &str| OpKind::Call(name.to_string());
+        table
+            .add(Operator::new(
+                "%%",
+                Fixity::Infix(Assoc::Left),
+                20,
+                call("rem"),
+            ))
+            .unwrap();
+        table
+            .add(Operator::new(
+                "mod",
+                Fixity::Infix(Assoc::Left),
+                20,
+                call("modulo"),
+            ))
+            .unwrap();
+        table
+            .add(Operator::new("?", Fixity::Postfix, 50, call("valid")))
+            .unwrap();
+        table
+            .add(Operator::new("not", Fixity::Prefix, 5, call("not")))
+            .unwrap();
+        assert_prints(&table, "rem(a, b + 1)", "a %% (b + 1)");
+        assert_prints(&table, "(a mod b) mod c", "a mod b mod c");
+        assert_prints(&table, "(a + b)?", "(a + b)?");
+        assert_prints(&table, "-(a?)", "-a?");
+        assert_prints(&table, "(-a)?", "(-a)?");
+        assert_prints(&table, "not a + 1", "not a + 1");
+        assert_prints(&table, "(not a) + 1", "(not a) + 1");
+        // named arguments keep the call
+        assert_prints(&table, "rem(a, b=2)", "rem(a, b=2)");
+        assert_eq!(
+            print_expression(
+                &Expression::BinOp(
+                    Box::new(Expression::Const(Literal::Ident("a".to_string()))),
+                    Box::new(Expression::Const(Literal::Number(2.0))),
+                    Operation::Power
+                ),
+                &table
+            ),
+            "a ** 2"
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let table = OperatorTable::default();
+        assert_eq!(
+            format_formula("# spread\n(&ask-&bid) /* avg */ / 2", &table),
+            Ok("(&ask - &bid) / 2".to_string())
+        );
+        assert_eq!(
+            format_definitions("def one()=1; def zscore(x,m,s)=((x-m)/s)", &table),
+            Ok("def one() = 1\ndef zscore(x, m, s) = (x - m) / s\n".to_string())
+        );
+        assert_eq!(
+            format_definitions("const K=(2*PI)", &table),
+            Ok("const K = 2 * PI\n".to_string())
+        );
+        assert!(format_formula("1 +", &table).is_err());
+    }
+}
@@ -4,6 +4,11 @@ pub use xmacro;
 
 /// Holds basic public API to the compiler and useful type declaraions
 pub mod prelude {
-    pub use types::{Callable, CallableType, Function, InterpreterContext, Type};
+    #[cfg(feature = "checkpoint")]
+    pub use types::{decode_state, encode_state};
+    pub use types::{
+        inventory, Arity, CallError, CallResult, Callable, CallableType, ExportedCallable,
+        Function, HasKind, InterpreterContext, IntoCallable, Kind, Param, Signature, Type,
+    };
     pub use xmacro::{resolve_name, runtime_callable, use_library};
 }
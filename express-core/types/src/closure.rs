@@ -0,0 +1,165 @@
+use crate::{
+    Arity, CallResult, Callable, CallableType, Function, HasKind, Kind, Signature, TimeStep, Type,
+};
+use std::{marker::PhantomData, sync::Arc};
+
+/// Value returned by a closure registered as a function.
+/// Returning `None` means there is no value to propagate further.
+pub trait CallOutput {
+    const KIND: Kind;
+
+    fn into_output(self) -> Option<Type>;
+}
+
+macro_rules! call_output {
+    ($($type:ty),*) => {
+        $(
+            impl CallOutput for $type {
+                const KIND: Kind = <$type as HasKind>::KIND;
+
+                fn into_output(self) -> Option<Type> {
+                    Some(self.into())
+                }
+            }
+
+            impl CallOutput for Option<$type> {
+                const KIND: Kind = <$type as HasKind>::KIND;
+
+                fn into_output(self) -> Option<Type> {
+                    self.map(Into::into)
+                }
+            }
+        )*
+    };
+}
+
+call_output!(
+    f64,
+    f32,
+    isize,
+    usize,
+    i32,
+    i64,
+    String,
+    TimeStep,
+    (f64, f64)
+);
+call_output!(Arc<[TimeStep]>, Arc<[f64]>, Vec<f64>, Function, Type);
+
+/// Converts a closure into a [Callable] object, so it can be registered
+/// without the `#[runtime_callable]` macro:
+/// `ctx.register_pure_fn("hypot", |x: f64, y: f64| x.hypot(y))`.
+/// Closures of up to 6 arguments are supported. Each argument must be
+/// convertible from `&Type` and the result must implement [CallOutput].
+/// The `Marker` is the signature of the closure, e.g. `fn(f64, f64) -> f64`.
+pub trait IntoCallable<Marker> {
+    fn into_callable(self, name: &'static str, of_type: CallableType) -> Box<dyn Callable>;
+}
+
+/// Callable object that wraps a closure
+pub struct FnCallable<F, Marker> {
+    name: &'static str,
+    of_type: CallableType,
+    func: F,
+    marker: PhantomData<Marker>,
+}
+
+macro_rules! closure_callable {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> IntoCallable<fn($($arg),*) -> R> for F
+        where
+            F: FnMut($($arg),*) -> R + 'static,
+            R: CallOutput + 'static,
+            $($arg: for<'t> From<&'t Type> + HasKind + 'static,)*
+        {
+            fn into_callable(self, name: &'static str, of_type: CallableType) -> Box<dyn Callable> {
+                Box::new(FnCallable {
+                    name,
+                    of_type,
+                    func: self,
+                    marker: PhantomData::<fn($($arg),*) -> R>,
+                })
+            }
+        }
+
+        impl<F, R, $($arg),*> Callable for FnCallable<F, fn($($arg),*) -> R>
+        where
+            F: FnMut($($arg),*) -> R + 'static,
+            R: CallOutput + 'static,
+            $($arg: for<'t> From<&'t Type> + HasKind + 'static,)*
+        {
+            fn name(&self) -> &'static str {
+                self.name
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&mut self, args: &[Type]) -> CallResult {
+                let mut args = args.iter();
+                let value = (self.func)($(<$arg as From<&Type>>::from(args.next().unwrap())),*);
+                Ok(value.into_output())
+            }
+
+            fn arity(&self) -> Arity {
+                Arity::exact(<[&str]>::len(&[$(stringify!($arg)),*]))
+            }
+
+            fn of_type(&self) -> CallableType {
+                self.of_type
+            }
+
+            fn signature(&self) -> Signature {
+                Signature {
+                    args: vec![$(<$arg as HasKind>::KIND),*],
+                    rest: None,
+                    params: Vec::new(),
+                    returns: R::KIND,
+                }
+            }
+        }
+    };
+}
+
+closure_callable!();
+closure_callable!(A);
+closure_callable!(A, B);
+closure_callable!(A, B, C);
+closure_callable!(A, B, C, D);
+closure_callable!(A, B, C, D, E);
+closure_callable!(A, B, C, D, E, G);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closure_callable() {
+        let mut f = (|x: f64, y: f64| x.hypot(y)).into_callable("hypot", CallableType::Pure);
+        assert_eq!(f.name(), "hypot");
+        assert_eq!(f.arity(), Arity::exact(2));
+        assert_eq!(f.of_type(), CallableType::Pure);
+        assert_eq!(f.signature().args, vec![Kind::Number, Kind::Number]);
+        assert_eq!(f.signature().returns, Kind::Number);
+        assert_eq!(
+            f.call(&[Type::Number(3.0), Type::Number(4.0)]),
+            Ok(Some(Type::Number(5.0)))
+        );
+    }
+
+    #[test]
+    fn test_stateful_closure() {
+        let mut count = 0;
+        let mut f = (move |text: String| {
+            count += 1;
+            (count > 1).then(|| text.repeat(count))
+        })
+        .into_callable("rep", CallableType::Stateful);
+        assert_eq!(f.signature().returns, Kind::String);
+        let args = [Type::String("a".to_string())];
+        assert_eq!(f.call(&args), Ok(None));
+        assert_eq!(f.call(&args), Ok(Some(Type::String("aa".to_string()))));
+
+        let mut f = (|| 42usize).into_callable("answer", CallableType::Const);
+        assert_eq!(f.arity(), Arity::exact(0));
+        assert_eq!(f.call(&[]), Ok(Some(Type::Number(42.0))));
+    }
+}
@@ -1,5 +1,12 @@
+mod closure;
+
+pub use closure::{CallOutput, FnCallable, IntoCallable};
+pub use inventory;
+#[cfg(feature = "checkpoint")]
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
-    cell::RefCell,
+    any::Any,
+    cell::{Ref, RefCell},
     fmt::{Debug, Display},
     rc::Rc,
     sync::Arc,
@@ -18,9 +25,25 @@ pub enum Type {
     Collection(Arc<[TimeStep]>),
     TimeStep(TimeStep),
     Function(Function),
+    Array(Arc<[f64]>),
     None,
 }
 
+impl Type {
+    /// Returns the kind of a value
+    pub fn kind(&self) -> Kind {
+        match self {
+            Type::Number(_) => Kind::Number,
+            Type::String(_) => Kind::String,
+            Type::Collection(_) => Kind::Collection,
+            Type::TimeStep(_) => Kind::TimeStep,
+            Type::Function(_) => Kind::Function,
+            Type::Array(_) => Kind::Array,
+            Type::None => Kind::None,
+        }
+    }
+}
+
 impl Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -29,11 +52,193 @@ impl Display for Type {
             Type::Collection(coll) => write!(f, "{:?}", *coll),
             Type::TimeStep(ts) => write!(f, "{}", ts),
             Type::Function(func) => write!(f, "{}", func),
+            Type::Array(arr) => write!(f, "{:?}", arr),
             Type::None => write!(f, "None"),
         }
     }
 }
 
+/// Describes a [Type] variant without its value.
+/// It is used to check formulas against function signatures
+/// before they get evaluated.
+/// * Any -- stands for a value whose kind is unknown or not restricted.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum Kind {
+    Number,
+    String,
+    Collection,
+    TimeStep,
+    Function,
+    Array,
+    None,
+    Any,
+}
+
+impl Kind {
+    /// Tells if a value of the `other` kind can be used where `self` is expected
+    #[inline]
+    pub fn accepts(&self, other: Kind) -> bool {
+        matches!(self, Kind::Any) || matches!(other, Kind::Any) || *self == other
+    }
+
+    /// Returns the fields accessible on the values of this kind with
+    /// `value.field` syntax. Non-record kinds have no fields
+    pub fn fields(&self) -> &'static [Field] {
+        match self {
+            Kind::TimeStep => &TIMESTEP_FIELDS,
+            _ => &[],
+        }
+    }
+
+    /// Returns all kinds which have fields
+    pub fn records() -> &'static [Kind] {
+        &[Kind::TimeStep]
+    }
+}
+
+/// Named field of a record kind like [TimeStep]
+#[derive(Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub kind: Kind,
+    /// Extracts the field from a value, returns `None` for the
+    /// values of a different kind
+    pub get: fn(&Type) -> Option<Type>,
+}
+
+static TIMESTEP_FIELDS: [Field; 2] = [
+    Field {
+        name: "price",
+        kind: Kind::Number,
+        get: |value| match value {
+            Type::TimeStep(ts) => Some(Type::Number(ts.price)),
+            _ => None,
+        },
+    },
+    Field {
+        name: "time",
+        kind: Kind::Number,
+        get: |value| match value {
+            Type::TimeStep(ts) => Some(Type::Number(ts.time)),
+            _ => None,
+        },
+    },
+];
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Associates a rust type with the [Kind] of a runtime [Type]
+/// it is converted from. It is used by the `#[runtime_callable]`
+/// macro to record function signatures.
+pub trait HasKind {
+    const KIND: Kind;
+}
+
+macro_rules! has_kind {
+    ($kind:path => $($t:ty),+) => {
+        $(impl HasKind for $t {
+            const KIND: Kind = $kind;
+        })+
+    };
+}
+
+has_kind!(Kind::Number => f64, f32, isize, usize, i32, i64);
+has_kind!(Kind::String => String);
+has_kind!(Kind::Collection => Arc<[TimeStep]>);
+has_kind!(Kind::TimeStep => TimeStep, (f64, f64));
+has_kind!(Kind::Function => Function);
+has_kind!(Kind::Array => Arc<[f64]>, Vec<f64>, &[f64]);
+has_kind!(Kind::Any => Type, &Type);
+
+impl<T: Callable> HasKind for T {
+    const KIND: Kind = Kind::Function;
+}
+
+/// Number of arguments a [Callable] object accepts.
+/// Variadic functions have no upper bound.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    /// Accepts exactly `n` arguments
+    pub const fn exact(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    /// Accepts `min` or more arguments
+    pub const fn variadic(min: usize) -> Self {
+        Self { min, max: None }
+    }
+
+    #[inline]
+    pub fn accepts(&self, argcnt: usize) -> bool {
+        argcnt >= self.min && self.max.is_none_or(|max| argcnt <= max)
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "from {} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// A named parameter of a [Callable] object.
+/// Arguments may be passed by its name: `jma(&px, len=20)`.
+/// Parameters with a default value can be omitted at call sites.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Param {
+    pub name: &'static str,
+    pub default: Option<Type>,
+}
+
+/// Parameter and return kinds of a [Callable] object.
+/// * rest -- kind of trailing variadic arguments if there are any.
+/// * params -- names and defaults of leading parameters.
+///   It is empty if parameters can't be passed by name.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Signature {
+    pub args: Vec<Kind>,
+    pub rest: Option<Kind>,
+    pub params: Vec<Param>,
+    pub returns: Kind,
+}
+
+impl Signature {
+    /// A signature that accepts any arguments within the given arity
+    pub fn any(arity: Arity) -> Self {
+        Self {
+            args: vec![Kind::Any; arity.max.unwrap_or(arity.min)],
+            rest: arity.max.is_none().then_some(Kind::Any),
+            params: Vec::new(),
+            returns: Kind::Any,
+        }
+    }
+
+    /// Returns the expected kind of an argument at `pos`
+    pub fn kind_at(&self, pos: usize) -> Option<Kind> {
+        self.args.get(pos).copied().or(self.rest)
+    }
+
+    /// Returns the position of a parameter with the given name
+    pub fn position_of(&self, name: &str) -> Option<usize> {
+        self.params.iter().position(|p| p.name == name)
+    }
+}
+
 /// A wrapping structure around `(f64, f64)` that represents
 /// a single tick of data with fields.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -53,12 +258,105 @@ pub trait InterpreterContext {
     /// Registers given function in the interpreter context
     fn register_function(&mut self, name: &str, exp_fn: Box<dyn Callable>);
 
-    /// Registers given named constant in the interpreter context
-    fn register_constant(&mut self, name: &str, exp_const: f64);
+    /// Registers given named constant of any type in the interpreter context
+    fn register_constant(&mut self, name: &str, exp_const: Type);
 
     fn find_function(&self, name: &str) -> Option<&Function>;
 
-    fn find_constant(&self, name: &str) -> Option<f64>;
+    /// Returns all registered overloads of a function
+    fn find_overloads(&self, name: &str) -> &[Function];
+
+    fn find_constant(&self, name: &str) -> Option<&Type>;
+
+    /// Registers a closure with side effects or a state of its own.
+    /// Its calls are never evaluated at build time:
+    /// `ctx.register_stateful_fn("noise", move |scale: f64| scale * rng.gen::<f64>())`
+    fn register_stateful_fn<Marker>(&mut self, name: &'static str, func: impl IntoCallable<Marker>)
+    where
+        Self: Sized,
+    {
+        self.register_function(name, func.into_callable(name, CallableType::Stateful));
+    }
+
+    /// Registers a closure without side effects. Its calls with constant
+    /// arguments are evaluated at build time.
+    fn register_pure_fn<Marker>(&mut self, name: &'static str, func: impl IntoCallable<Marker>)
+    where
+        Self: Sized,
+    {
+        self.register_function(name, func.into_callable(name, CallableType::Pure));
+    }
+
+    /// Registers every exported callable of the module and its submodules
+    /// under its name qualified by the module path without the crate name:
+    /// `register_module("express_std::timeseries")` registers `timeseries::ema::ema`.
+    fn register_module(&mut self, path: &str) -> Result<(), String>
+    where
+        Self: Sized,
+    {
+        let prefix = match path.split_once("::") {
+            Some((_, namespace)) => format!("{}::", namespace),
+            None => String::new(),
+        };
+        self.register_module_as(path, &prefix)
+    }
+
+    /// Registers every exported callable of the module and its submodules
+    /// under its path relative to the module with the `prefix` prepended:
+    /// `register_module_as("express_std::math", "m::")` registers `m::common::max`.
+    fn register_module_as(&mut self, path: &str, prefix: &str) -> Result<(), String>
+    where
+        Self: Sized,
+    {
+        register_exported(self, path, |item| {
+            match item.module[path.len()..].trim_start_matches("::") {
+                "" => format!("{}{}", prefix, item.name),
+                module => format!("{}{}::{}", prefix, module, item.name),
+            }
+        })
+    }
+
+    /// Registers every exported callable of the module and its submodules
+    /// under its own name with the `prefix` prepended, like `use module::*;`
+    fn import_module(&mut self, path: &str, prefix: &str) -> Result<(), String>
+    where
+        Self: Sized,
+    {
+        register_exported(self, path, |item| format!("{}{}", prefix, item.name))
+    }
+}
+
+/// Registers exported callables of the module under the given names.
+/// A name that already has another overload of the same signature is an
+/// error, while registering the same callable again replaces it.
+fn register_exported(
+    ctx: &mut impl InterpreterContext,
+    path: &str,
+    name_of: impl Fn(&ExportedCallable) -> String,
+) -> Result<(), String> {
+    let mut found = false;
+    for item in ExportedCallable::of_module(path) {
+        let name = name_of(item);
+        let func = Function::from_callable((item.make)());
+        let args = func.signature().args;
+        let clash = ctx
+            .find_overloads(&name)
+            .iter()
+            .any(|f| f.signature().args == args && !f.is_same_callable(&func));
+        if clash {
+            return Err(format!(
+                "Function '{}' from module '{}' is already registered with the same signature",
+                name, item.module
+            ));
+        }
+        ctx.register_function(&name, (item.make)());
+        found = true;
+    }
+    if found {
+        Ok(())
+    } else {
+        Err(format!("Module '{}' has no exported callables", path))
+    }
 }
 
 /// Represents a general runtime concept of a function.
@@ -72,11 +370,15 @@ pub type SharedFunction = Rc<RefCell<dyn Callable>>;
 #[derive(Clone)]
 pub struct Function {
     inner: SharedFunction,
+    name: &'static str,
+    of_type: CallableType,
 }
 
 impl Function {
     pub fn from_callable(call_obj: Box<dyn Callable>) -> Self {
         Self {
+            name: call_obj.name(),
+            of_type: call_obj.of_type(),
             inner: call_obj.wrap_in_refcell(),
         }
     }
@@ -85,21 +387,50 @@ impl Function {
         self.inner.clone()
     }
 
-    /// Uses interior mutability pattern to dispatch a fn call
+    /// Tells if both functions are made of the same callable type
+    pub fn is_same_callable(&self, other: &Function) -> bool {
+        let type_of = |f: &Function| Any::type_id(f.inner.borrow().as_any());
+        type_of(self) == type_of(other)
+    }
+
+    /// Borrows the callable object if it is of type `T`
+    pub fn downcast_ref<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.inner.try_borrow().ok()?, |f| f.as_any().downcast_ref()).ok()
+    }
+
+    /// Uses interior mutability pattern to dispatch a fn call.
+    /// Functions without state are called through a shared reference, so they
+    /// may be called again while still running, e.g. by a lambda they have
+    /// been given. A running stateful function can't be called again.
     #[inline(always)]
-    pub fn call(&self, args: &[Type]) -> Option<Type> {
-        self.inner.borrow_mut().call(args)
+    pub fn call(&self, args: &[Type]) -> CallResult {
+        if self.can_be_optimized() {
+            if let Some(result) = self
+                .inner
+                .try_borrow()
+                .ok()
+                .and_then(|f| f.call_shared(args))
+            {
+                return result;
+            }
+        }
+        match self.inner.try_borrow_mut() {
+            Ok(mut f) => f.call(args),
+            Err(_) => Err(CallError::new(
+                self.name,
+                "function is called again while it is still running",
+            )),
+        }
     }
 
     #[inline(always)]
     pub fn name(&self) -> &'static str {
-        let name = self.inner.borrow().name().clone();
-        name
+        self.name
     }
 
     #[inline(always)]
-    pub fn argcnt(&self) -> usize {
-        self.inner.borrow().argcnt()
+    pub fn arity(&self) -> Arity {
+        self.inner.borrow().arity()
     }
 
     #[inline(always)]
@@ -115,7 +446,22 @@ impl Function {
 
     #[inline(always)]
     pub fn of_type(&self) -> CallableType {
-        self.inner.borrow().of_type()
+        self.of_type
+    }
+
+    #[inline(always)]
+    pub fn signature(&self) -> Signature {
+        self.inner.borrow().signature()
+    }
+
+    /// See [Callable::save_state]
+    pub fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+        self.inner.borrow().save_state()
+    }
+
+    /// See [Callable::load_state]
+    pub fn load_state(&self, state: &[u8]) -> Result<(), String> {
+        self.inner.borrow_mut().load_state(state)
     }
 }
 
@@ -137,6 +483,38 @@ impl PartialEq for Function {
     }
 }
 
+/// An error reported by a [Callable] object when it fails to
+/// produce a result for the given arguments.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CallError {
+    /// Name of the function that failed
+    pub func: &'static str,
+    pub reason: String,
+}
+
+impl CallError {
+    pub fn new(func: &'static str, reason: impl Display) -> Self {
+        Self {
+            func,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fn {}(...) failed: {}", self.func, self.reason)
+    }
+}
+
+impl std::error::Error for CallError {}
+
+/// Result of a [Callable] invocation.
+/// * `Ok(Some(_))` -- function produced a value;
+/// * `Ok(None)` -- function has nothing to return yet (e.g. not enough data);
+/// * `Err(_)` -- function failed.
+pub type CallResult = Result<Option<Type>, CallError>;
+
 /// This is a public Callable trait which lets
 /// any function be runable inside.
 /// ## Why?
@@ -151,10 +529,20 @@ pub trait Callable: CallableWrapper {
     /// Returns the name of an object.
     fn name(&self) -> &'static str;
 
-    fn call(&mut self, args: &[Type]) -> Option<Type>;
+    /// Invokes the function. Returning `Ok(None)` is not an error,
+    /// it signals that there is no value to propagate further.
+    fn call(&mut self, args: &[Type]) -> CallResult;
+
+    /// Invokes a `pure` or `const` function through a shared reference, so
+    /// that nested calls like `map(|x| dot(map(|y| x * y, ..), ..), ..)` don't
+    /// need exclusive access to the same object. Returns `None` if the
+    /// function can only be invoked with `call`, which is the default.
+    fn call_shared(&self, _args: &[Type]) -> Option<CallResult> {
+        None
+    }
 
     /// Returns a number of arguments the function expects
-    fn argcnt(&self) -> usize;
+    fn arity(&self) -> Arity;
 
     /// Signifies if the Callable object stands for a pure function.
     /// If all of its arguments are Const as well (or pure functions with const args).
@@ -162,11 +550,44 @@ pub trait Callable: CallableWrapper {
     fn of_type(&self) -> CallableType {
         CallableType::Stateful
     }
+
+    /// Describes kinds of arguments and the return value.
+    /// By default the function accepts and returns anything.
+    fn signature(&self) -> Signature {
+        Signature::any(self.arity())
+    }
+
+    /// Captures the inner state of a stateful function, so that
+    /// it survives a restart. `Ok(None)`, the default, tells that the
+    /// state can't be saved: checkpoints of stateful calls fail then.
+    fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+        Ok(None)
+    }
+
+    /// Replaces the inner state with the one produced by `save_state`.
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), String> {
+        Err(format!("{} has no state to restore", self.name()))
+    }
+}
+
+/// Serializes a state for [Callable::save_state]
+#[cfg(feature = "checkpoint")]
+pub fn encode_state<T: Serialize>(state: &T) -> Result<Vec<u8>, String> {
+    bincode::serialize(state).map_err(|e| e.to_string())
+}
+
+/// Deserializes a state saved with [encode_state]
+#[cfg(feature = "checkpoint")]
+pub fn decode_state<T: DeserializeOwned>(state: &[u8]) -> Result<T, String> {
+    bincode::deserialize(state).map_err(|e| e.to_string())
 }
 
 /// Helps to convert a Boxed Callable object to an Rc
+/// and to get the concrete object back out of a [Function]
 pub trait CallableWrapper {
     fn wrap_in_refcell(self: Box<Self>) -> Rc<RefCell<dyn Callable>>;
+
+    fn as_any(&self) -> &dyn Any;
 }
 
 impl<T> CallableWrapper for T
@@ -176,13 +597,41 @@ where
     fn wrap_in_refcell(self: Box<Self>) -> Rc<RefCell<dyn Callable>> {
         Rc::new(RefCell::new(*self))
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Callable submitted into the link-time registry by `#[runtime_callable(export)]`.
+/// Contexts pick them up by the module they are defined in,
+/// see [InterpreterContext::register_module].
+pub struct ExportedCallable {
+    /// Module the function is defined in: `express_std::timeseries::ema`
+    pub module: &'static str,
+    pub name: &'static str,
+    pub make: fn() -> Box<dyn Callable>,
+}
+
+inventory::collect!(ExportedCallable);
+
+impl ExportedCallable {
+    /// Returns callables exported from the module and its submodules
+    pub fn of_module(module: &str) -> impl Iterator<Item = &'static ExportedCallable> + '_ {
+        inventory::iter::<ExportedCallable>
+            .into_iter()
+            .filter(move |item| match item.module.strip_prefix(module) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            })
+    }
 }
 
 /// Marks callable object type.
 /// * Stateful - functions that may carry inner mutable state. They are the reason `call(...)` accepts `&mut self`.
 /// * Const - functions that will be evaluated at _"compile time"_. No mater what.
 /// * Pure - trivial functions without side effects that may be inlined at _"compile time"_
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CallableType {
     Stateful,
     Const,
@@ -217,6 +666,7 @@ bijection!(Type::String => String);
 bijection!(Type::Function => Function);
 bijection!(Type::TimeStep => TimeStep);
 bijection!(Type::Collection => Arc<[TimeStep]>);
+bijection!(Type::Array => Arc<[f64]>);
 
 impl<T: 'static + Callable> From<T> for Type {
     fn from(c: T) -> Self {
@@ -224,6 +674,18 @@ impl<T: 'static + Callable> From<T> for Type {
     }
 }
 
+impl From<&str> for Type {
+    fn from(val: &str) -> Self {
+        Type::String(val.to_string())
+    }
+}
+
+impl<const N: usize> From<[f64; N]> for Type {
+    fn from(val: [f64; N]) -> Self {
+        Type::Array(Arc::new(val))
+    }
+}
+
 impl From<&Type> for Type {
     fn from(val: &Type) -> Self {
         val.clone()
@@ -266,6 +728,42 @@ impl From<&Type> for String {
     }
 }
 
+impl From<&Type> for Arc<[f64]> {
+    fn from(val: &Type) -> Self {
+        match val {
+            Type::Array(arr) => arr.clone(),
+            _ => panic!("Recieved unrecognized type"),
+        }
+    }
+}
+
+impl<'a> From<&'a Type> for &'a [f64] {
+    fn from(val: &'a Type) -> Self {
+        match val {
+            Type::Array(arr) => arr,
+            _ => panic!("Recieved unrecognized type"),
+        }
+    }
+}
+
+impl From<&Type> for Vec<f64> {
+    fn from(val: &Type) -> Self {
+        <&[f64]>::from(val).to_vec()
+    }
+}
+
+impl From<Type> for Vec<f64> {
+    fn from(val: Type) -> Self {
+        Vec::from(&val)
+    }
+}
+
+impl From<Vec<f64>> for Type {
+    fn from(val: Vec<f64>) -> Self {
+        Type::Array(val.into())
+    }
+}
+
 impl From<&Type> for Arc<[TimeStep]> {
     fn from(val: &Type) -> Self {
         match val {
@@ -356,6 +854,32 @@ mod tests {
         test_bijection!((0.1, 2.2), (f64, f64));
     }
 
+    #[test]
+    fn test_array() {
+        test_bijection!(Arc::<[f64]>::from([1.0, 2.0]), Arc<[f64]>);
+        test_bijection!(vec![1.0, 2.0], Vec<f64>);
+        let arr = Type::from(vec![1.0, 2.0]);
+        assert_eq!(arr.kind(), Kind::Array);
+        assert_eq!(<&[f64]>::from(&arr), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_fields() {
+        let ts = Type::from(TimeStep {
+            price: 22.0,
+            time: 11.0,
+        });
+        let fields = Kind::TimeStep.fields();
+        assert_eq!(
+            fields.iter().map(|f| f.name).collect::<Vec<_>>(),
+            vec!["price", "time"]
+        );
+        assert_eq!((fields[0].get)(&ts), Some(Type::Number(22.0)));
+        assert_eq!((fields[1].get)(&ts), Some(Type::Number(11.0)));
+        assert_eq!((fields[0].get)(&Type::Number(1.0)), None);
+        assert!(Kind::Number.fields().is_empty());
+    }
+
     #[test]
     fn test_timestep() {
         test_bijection!(
@@ -5,26 +5,72 @@ use quote::{format_ident, quote};
 use syn::{parse_macro_input, spanned::Spanned, FnArg, Pat, ReturnType};
 use uselib::Library;
 
-fn parse_flag_attr(attr: TokenStream) -> Result<TStream, syn::Error> {
-    match syn::parse::<syn::Ident>(attr) {
-        Ok(tt) => match tt.to_string().as_str() {
-            "pure" => Ok(quote!(CallableType::Pure)),
-            "constant" => Ok(quote!(CallableType::Const)),
-            "" => Ok(quote!(CallableType::Stateful)),
-            _ => Err(syn::Error::new(
-                tt.span(),
-                format!("Macro accepts only: [pure, constant]"),
-            )),
-        },
-        Err(_) => Ok(quote!(CallableType::Stateful)),
+/// Flags of the `runtime_callable` attribute
+struct Flags {
+    mode: TStream,
+    /// Submits the callable into the link-time registry
+    export: bool,
+    /// Expands an impl block with a `step` method
+    stateful: bool,
+    /// Saves and loads the state of a stateful callable with serde
+    checkpoint: bool,
+}
+
+fn parse_flag_attr(attr: TokenStream) -> Result<Flags, syn::Error> {
+    use syn::{ext::IdentExt, parse::Parser, punctuated::Punctuated};
+    // keywords like `const` are accepted as flag names as well
+    let parser = |input: syn::parse::ParseStream| {
+        Punctuated::<syn::Ident, syn::Token![,]>::parse_terminated_with(
+            input,
+            syn::Ident::parse_any,
+        )
+    };
+    let idents = parser.parse(attr)?;
+    let mut flags = Flags {
+        mode: quote!(CallableType::Stateful),
+        export: false,
+        stateful: false,
+        checkpoint: false,
+    };
+    for tt in idents {
+        match tt.to_string().as_str() {
+            "pure" => flags.mode = quote!(CallableType::Pure),
+            "constant" | "const" => flags.mode = quote!(CallableType::Const),
+            "stateful" => flags.stateful = true,
+            "export" => flags.export = true,
+            "checkpoint" => flags.checkpoint = true,
+            _ => {
+                return Err(syn::Error::new(
+                    tt.span(),
+                    "Macro accepts only: [pure, constant, stateful, export, checkpoint]"
+                        .to_string(),
+                ))
+            }
+        }
+    }
+    if flags.stateful && flags.mode.to_string() != quote!(CallableType::Stateful).to_string() {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "Stateful callable cannot be pure or constant",
+        ));
     }
+    if flags.checkpoint && !flags.stateful {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "Only a stateful callable has a state to checkpoint",
+        ));
+    }
+    Ok(flags)
 }
 
 fn mangle_struct_name(name: syn::Ident) -> syn::Ident {
     format_ident!("__{}", name)
 }
 
-fn extract_type_from_option(ty: &syn::Type) -> Option<&syn::Type> {
+/// Extracts the first generic argument of a `wrapper` type.
+/// For example, `String` of `Option<String>` if `wrapper` is `"Option"`.
+/// Only bare names and `std`/`core` paths are recognized, e.g. `std::option::Option`.
+fn extract_generic_type<'t>(ty: &'t syn::Type, wrapper: &str) -> Option<&'t syn::Type> {
     use syn::{GenericArgument, Path, PathArguments, PathSegment};
 
     fn extract_type_path(ty: &syn::Type) -> Option<&Path> {
@@ -34,26 +80,26 @@ fn extract_type_from_option(ty: &syn::Type) -> Option<&syn::Type> {
         }
     }
 
-    // TODO store (with lazy static) the vec of string
     // TODO maybe optimization, reverse the order of segments
-    fn extract_option_segment(path: &Path) -> Option<&PathSegment> {
-        let idents_of_path = path
-            .segments
-            .iter()
-            .into_iter()
-            .fold(String::new(), |mut acc, v| {
-                acc.push_str(&v.ident.to_string());
-                acc.push('|');
-                acc
-            });
-        vec!["Option|", "std|option|Option|", "core|option|Option|"]
-            .into_iter()
-            .find(|s| &idents_of_path == *s)
-            .and_then(|_| path.segments.last())
+    fn extract_wrapper_segment<'p>(path: &'p Path, wrapper: &str) -> Option<&'p PathSegment> {
+        let idents_of_path = path.segments.iter().fold(String::new(), |mut acc, v| {
+            acc.push_str(&v.ident.to_string());
+            acc.push('|');
+            acc
+        });
+        let module = wrapper.to_lowercase();
+        [
+            format!("{wrapper}|"),
+            format!("std|{module}|{wrapper}|"),
+            format!("core|{module}|{wrapper}|"),
+        ]
+        .into_iter()
+        .find(|s| idents_of_path == *s)
+        .and_then(|_| path.segments.last())
     }
 
     extract_type_path(ty)
-        .and_then(|path| extract_option_segment(path))
+        .and_then(|path| extract_wrapper_segment(path, wrapper))
         .and_then(|path_seg| {
             let type_params = &path_seg.arguments;
             // It should have only on angle-bracketed param ("<String>"):
@@ -84,61 +130,294 @@ fn extract_type_from_option(ty: &syn::Type) -> Option<&syn::Type> {
 /// ```ignore
 /// use types::{Callable, Type};
 /// #[allow(non_camel_case_types)]
-/// struct _foo;
-/// impl Callable for _foo{
-///     fn call(&self, args: Box<[Type]>) -> Type {
+/// struct __foo;
+/// impl __foo {
+///     fn invoke(&self, args: &[Type]) -> CallResult {
 ///         let input: f64 = unsafe { args.get_unchecked(0usize).into() };
-///         { input + 3.14f64 }.into()
+///         Ok(Some(foo(input).into()))
+///     }
+/// }
+/// impl Callable for __foo {
+///     fn call(&mut self, args: &[Type]) -> CallResult {
+///         self.invoke(args)
+///     }
+///     // `pure` and `constant` functions are invoked through `&self` as well
+///     fn call_shared(&self, args: &[Type]) -> Option<CallResult> {
+///         Some(self.invoke(args))
+///     }
+/// }
+/// ```
+/// The `export` flag, as in `#[runtime_callable(pure, export)]`, submits the callable
+/// into a link-time registry, so it is picked up along with its module by
+/// `InterpreterContext::register_module` or a `module::*;` entry of `use_library!`.
+///
+/// Stateful functions are written as an impl block marked with `stateful`.
+/// The associated function returning `Self` is registered under its own name
+/// and constructs the state at build time from the leading arguments.
+/// `step` receives the rest of them each time the formula is evaluated:
+/// ```ignore
+/// pub struct Accumulate {
+///     acc: f64,
+/// }
+///
+/// #[runtime_callable(stateful, export)]
+/// impl Accumulate {
+///     pub fn acc(init: f64) -> Self {
+///         Accumulate { acc: init }
+///     }
+///
+///     fn step(&mut self, value: f64) -> f64 {
+///         self.acc += value;
+///         self.acc
 ///     }
 /// }
 /// ```
+/// So `acc(0, &px)` is a running sum of `&px`.
+/// With `#[runtime_callable(stateful, checkpoint)]` the state is also saved and
+/// restored by the interpreter checkpoints, which requires `Serialize` and
+/// `Deserialize` implementations of the type and the `checkpoint` feature
+/// of the `types` crate.
+///
+/// Function may return `T`, `Option<T>`, `Result<T, E>` or `Result<Option<T>, E>`
+/// where `E: Display`. An error is reported as a [CallError](types::CallError)
+/// that carries the name of the function.
 /// ## Safety:
 /// `unsafe` block helps to remove unnecessary bounds checks which are preformed
 /// at runtime before that.
 #[proc_macro_attribute]
 pub fn runtime_callable(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let mode = match parse_flag_attr(attr) {
-        Ok(pure) => pure,
+    let flags = match parse_flag_attr(attr) {
+        Ok(flags) => flags,
         Err(e) => return e.to_compile_error().into(),
     };
-    parse_function(item, mode)
+    if flags.stateful {
+        parse_stateful_impl(item, flags)
+    } else {
+        parse_function(item, flags)
+    }
 }
 
-fn parse_function(item: TokenStream, expr_type: TStream) -> TokenStream {
-    let function: syn::ItemFn = syn::parse_macro_input!(item);
-    let mut arguments: Vec<_> = Vec::new();
-    let mut argcnt: usize = 0;
-    for arg in function.sig.inputs.clone().into_iter() {
-        if let FnArg::Typed(t) = arg.clone() {
-            if let Pat::Ident(id) = *t.pat.clone() {
-                let tp = t.ty;
-                let q = quote! {
-                    let #id : #tp = unsafe { args.get_unchecked(#argcnt).into() };
-                };
-                arguments.push(q);
-            } else {
-                return syn::Error::new(
-                    t.span(),
-                    "This macro expects identifier as an argument name.",
-                )
-                .to_compile_error()
-                .into();
-            }
+/// Converts the value returned by the user function into a `CallResult`
+fn wrap_return_value(ret_t: &syn::Type, call: TStream) -> TStream {
+    if let Some(ok_t) = extract_generic_type(ret_t, "Result") {
+        let ok_value = if extract_generic_type(ok_t, "Option").is_some() {
+            quote! { Ok(value.map(Into::into)) }
         } else {
-            return syn::Error::new(
+            quote! { Ok(Some(value.into())) }
+        };
+        quote! {
+            match #call {
+                Ok(value) => #ok_value,
+                Err(err) => Err(CallError::new(self.name(), err)),
+            }
+        }
+    } else if extract_generic_type(ret_t, "Option").is_some() {
+        quote! { Ok(#call.map(Into::into)) }
+    } else {
+        quote! { Ok(Some(#call.into())) }
+    }
+}
+
+/// Strips `Result` and `Option` wrappers off the returned type
+fn returned_value_type(ret_t: &syn::Type) -> &syn::Type {
+    let ret_t = extract_generic_type(ret_t, "Result").unwrap_or(ret_t);
+    extract_generic_type(ret_t, "Option").unwrap_or(ret_t)
+}
+
+/// Recognizes a trailing parameter that collects the rest of arguments.
+/// Returns its value conversion and the kind of collected arguments.
+/// * `&[Type]` -- takes arguments as is;
+/// * `#[variadic] Vec<T>` -- converts each argument into `T`.
+///
+/// A `Vec<T>` without the attribute is a regular parameter taking an array.
+fn variadic_param(ty: &syn::Type, marked: bool, from: usize) -> Option<(TStream, TStream)> {
+    match ty {
+        syn::Type::Reference(r) => match &*r.elem {
+            syn::Type::Slice(s) if quote!(#s).to_string() == "[Type]" => {
+                Some((quote! { &args[#from..] }, quote! { Kind::Any }))
+            }
+            _ => None,
+        },
+        _ if marked => extract_generic_type(ty, "Vec").map(|item| {
+            (
+                quote! { args[#from..].iter().map(Into::into).collect() },
+                quote! { <#item as HasKind>::KIND },
+            )
+        }),
+        _ => None,
+    }
+}
+
+/// Takes `#[variadic]` attribute off the parameter, returns whether it was there.
+fn take_variadic_attr(param: &mut syn::PatType) -> bool {
+    let len = param.attrs.len();
+    param.attrs.retain(|a| !a.path.is_ident("variadic"));
+    param.attrs.len() != len
+}
+
+/// Takes `#[default = <literal>]` attribute off the parameter and returns
+/// the default value converted into a runtime `Type`.
+fn take_default_attr(param: &mut syn::PatType) -> Result<TStream, syn::Error> {
+    let pos = match param.attrs.iter().position(|a| a.path.is_ident("default")) {
+        Some(pos) => pos,
+        None => return Ok(quote! { None }),
+    };
+    let attr = param.attrs.remove(pos);
+    let tp = &param.ty;
+    match attr.parse_meta()? {
+        syn::Meta::NameValue(syn::MetaNameValue {
+            lit: syn::Lit::Str(s),
+            ..
+        }) => Ok(quote! { Some(Type::String(#s.to_string())) }),
+        syn::Meta::NameValue(syn::MetaNameValue { lit, .. }) => Ok(quote! {
+            Some({
+                let value: #tp = #lit;
+                Type::from(value)
+            })
+        }),
+        meta => Err(syn::Error::new(
+            meta.span(),
+            "Expected a default value like: #[default = 0.0]",
+        )),
+    }
+}
+
+/// Parameters of a user function converted from callable arguments
+struct Params {
+    /// Statements that bind arguments to parameter names
+    conversions: Vec<TStream>,
+    names: Vec<syn::Ident>,
+    /// Types of the parameters that take a single argument
+    types: Vec<syn::Type>,
+    /// Names and default values of the same parameters
+    params: Vec<TStream>,
+    /// Kind of arguments collected by a trailing variadic parameter
+    rest: Option<TStream>,
+}
+
+/// Collects parameters of a function whose first argument is at `offset`.
+/// Only the last parameter may be variadic and only if `variadic` is set.
+fn parse_params<'a>(
+    inputs: impl Iterator<Item = &'a mut FnArg>,
+    offset: usize,
+    variadic: bool,
+) -> Result<Params, syn::Error> {
+    let inputs: Vec<_> = inputs.collect();
+    let mut parsed = Params {
+        conversions: vec![],
+        names: vec![],
+        types: vec![],
+        params: vec![],
+        rest: None,
+    };
+    let inputs_len = inputs.len();
+    for (pos, arg) in inputs.into_iter().enumerate() {
+        let FnArg::Typed(t) = arg else {
+            return Err(syn::Error::new(
                 arg.span(),
                 "This macro cannot be applied to functions that use Reciever types like `self`",
-            )
-            .to_compile_error()
-            .into();
+            ));
+        };
+        let default = take_default_attr(t)?;
+        let marked = take_variadic_attr(t);
+        let Pat::Ident(id) = &*t.pat else {
+            return Err(syn::Error::new(
+                t.span(),
+                "This macro expects identifier as an argument name.",
+            ));
+        };
+        let tp = &t.ty;
+        let id = id.ident.clone();
+        let argcnt = offset + pos;
+        let last = variadic && pos + 1 == inputs_len;
+        if marked && !last {
+            return Err(syn::Error::new(
+                t.span(),
+                "Only the last parameter of a function may be #[variadic]",
+            ));
+        }
+        let rest = if last {
+            variadic_param(tp, marked, argcnt)
+        } else {
+            None
+        };
+        if marked && rest.is_none() {
+            return Err(syn::Error::new(
+                t.span(),
+                "#[variadic] parameter is expected to be a `Vec<T>`",
+            ));
+        }
+        if let Some((value, kind)) = rest {
+            parsed.conversions.push(quote! { let #id : #tp = #value; });
+            parsed.names.push(id);
+            parsed.rest = Some(kind);
+            break;
         }
-        argcnt += 1;
+        let name = id.to_string();
+        parsed.conversions.push(quote! {
+            let #id : #tp = unsafe { args.get_unchecked(#argcnt).into() };
+        });
+        parsed
+            .params
+            .push(quote! { Param { name: #name, default: #default } });
+        parsed.names.push(id);
+        parsed.types.push((**tp).clone());
     }
+    Ok(parsed)
+}
+
+/// Builds the arity and the kind of the rest arguments of a callable
+fn arity_of(argcnt: usize, rest: &Option<TStream>) -> (TStream, TStream) {
+    match rest {
+        Some(kind) => (quote! { Arity::variadic(#argcnt) }, quote! { Some(#kind) }),
+        None => (quote! { Arity::exact(#argcnt) }, quote! { None }),
+    }
+}
+
+fn parse_function(item: TokenStream, flags: Flags) -> TokenStream {
+    let expr_type = flags.mode.clone();
+    let mut function: syn::ItemFn = syn::parse_macro_input!(item);
+    let Params {
+        conversions: arguments,
+        names: arg_names,
+        types: arg_types,
+        params,
+        rest: rest_kind,
+    } = match parse_params(function.sig.inputs.iter_mut(), 0, true) {
+        Ok(params) => params,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let argcnt = arg_types.len();
+    // functions without state may be called while they are still running,
+    // while the stateful ones keep no state of their own to checkpoint
+    let call_shared = if flags.mode.to_string() != quote!(CallableType::Stateful).to_string() {
+        quote! {
+            #[inline(always)]
+            fn call_shared(&self, args: &[Type]) -> Option<CallResult> {
+                Some(self.invoke(args))
+            }
+        }
+    } else {
+        quote! {
+            fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+                Ok(Some(Vec::new()))
+            }
+
+            fn load_state(&mut self, _state: &[u8]) -> Result<(), String> {
+                Ok(())
+            }
+        }
+    };
     let fn_name = mangle_struct_name(function.sig.ident.clone());
     let fn_src_name = function.sig.ident.clone();
+    let export = export_callable(&flags, &fn_name, &fn_src_name);
     let attrs = function.attrs.clone();
-    let result_type = if let ReturnType::Type(_, ref ret_t) = function.sig.output {
-        extract_type_from_option(&*ret_t)
+    let (arity, rest) = arity_of(argcnt, &rest_kind);
+    let (call_ret_stmt, ret_value_t) = if let ReturnType::Type(_, ref ret_t) = function.sig.output {
+        (
+            wrap_return_value(ret_t, quote! { #fn_src_name(#( #arg_names ),*) }),
+            returned_value_type(ret_t),
+        )
     } else {
         return syn::Error::new(
             function.sig.output.span(),
@@ -147,14 +426,6 @@ fn parse_function(item: TokenStream, expr_type: TStream) -> TokenStream {
         .to_compile_error()
         .into();
     };
-    let stmts = function.block.stmts.clone();
-
-    // This allows macro to handle returns like Option<T> and T
-    let call_ret_stmt = if let Some(_) = result_type {
-        quote! { Some( {#( #stmts )*}?.into() ) }
-    } else {
-        quote! { Some( {#( #stmts )*}.into() ) }
-    };
 
     quote! {
         // use express::types::{Callable, Type};
@@ -165,12 +436,21 @@ fn parse_function(item: TokenStream, expr_type: TStream) -> TokenStream {
         #[allow(non_camel_case_types)]
         pub struct #fn_name;
 
-        impl Callable for #fn_name {
+        impl #fn_name {
             #( #attrs )*
-            fn call(&mut self, args: &[Type]) -> Option<Type> {
+            fn invoke(&self, args: &[Type]) -> CallResult {
                 #( #arguments )*
                 #call_ret_stmt
             }
+        }
+
+        impl Callable for #fn_name {
+            #[inline(always)]
+            fn call(&mut self, args: &[Type]) -> CallResult {
+                self.invoke(args)
+            }
+
+            #call_shared
 
             #[inline(always)]
             fn name(&self) -> &'static str {
@@ -178,8 +458,8 @@ fn parse_function(item: TokenStream, expr_type: TStream) -> TokenStream {
             }
 
             #[inline(always)]
-            fn argcnt(&self) -> usize {
-                #argcnt
+            fn arity(&self) -> Arity {
+                #arity
             }
 
             #[inline(always)]
@@ -187,11 +467,235 @@ fn parse_function(item: TokenStream, expr_type: TStream) -> TokenStream {
                 #expr_type
             }
 
+            fn signature(&self) -> Signature {
+                Signature {
+                    args: vec![#( <#arg_types as HasKind>::KIND ),*],
+                    rest: #rest,
+                    params: vec![#( #params ),*],
+                    returns: <#ret_value_t as HasKind>::KIND,
+                }
+            }
+        }
+
+        #export
+    }
+    .into()
+}
+
+/// Tells if the type is `Self` or the type the impl block is for
+fn is_self_type(ty: &syn::Type, self_ty: &syn::Type) -> bool {
+    let ty = quote!(#ty).to_string();
+    ty == "Self" || ty == quote!(#self_ty).to_string()
+}
+
+/// Expands an impl block of a stateful callable. The constructor is the
+/// associated function that returns `Self`. It takes the leading arguments,
+/// which must be known at build time. `step` takes the rest of them on every call.
+fn parse_stateful_impl(item: TokenStream, flags: Flags) -> TokenStream {
+    let mut block: syn::ItemImpl = syn::parse_macro_input!(item);
+    if block.trait_.is_some() || !block.generics.params.is_empty() {
+        return syn::Error::new(
+            block.self_ty.span(),
+            "Stateful callable must be an inherent impl of a non-generic type",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let self_ty = block.self_ty.clone();
+    let mut ctor = None;
+    let mut step = None;
+    for item in block.items.iter_mut() {
+        let syn::ImplItem::Method(method) = item else {
+            continue;
+        };
+        if method.sig.ident == "step" {
+            step = Some(&mut method.sig);
+            continue;
+        }
+        let returns_self = match &method.sig.output {
+            ReturnType::Type(_, ret_t) => is_self_type(returned_value_type(ret_t), &self_ty),
+            ReturnType::Default => false,
+        };
+        if returns_self && method.sig.receiver().is_none() {
+            if let Some(other) = ctor.replace(&mut method.sig) {
+                return syn::Error::new(
+                    other.ident.span(),
+                    "Stateful callable must have a single constructor returning Self",
+                )
+                .to_compile_error()
+                .into();
+            }
+        }
+    }
+    let (Some(ctor), Some(step)) = (ctor, step) else {
+        return syn::Error::new(
+            self_ty.span(),
+            "Stateful callable needs a constructor returning Self and `fn step(&mut self, ...)`",
+        )
+        .to_compile_error()
+        .into();
+    };
+    let is_mut_self = matches!(
+        step.receiver(),
+        Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_some()
+    );
+    if !is_mut_self {
+        return syn::Error::new(step.span(), "Method `step` must take `&mut self`")
+            .to_compile_error()
+            .into();
+    }
+    let ctor_params = match parse_params(ctor.inputs.iter_mut(), 0, false) {
+        Ok(params) => params,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let argcnt = ctor_params.types.len();
+    let step_params = match parse_params(step.inputs.iter_mut().skip(1), argcnt, true) {
+        Ok(params) => params,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let ReturnType::Type(_, step_ret_t) = &step.output else {
+        return syn::Error::new(step.span(), "Function must be explicit in its return type")
+            .to_compile_error()
+            .into();
+    };
+    let ReturnType::Type(_, ctor_ret_t) = &ctor.output else {
+        unreachable!()
+    };
+
+    let ctor_src_name = ctor.ident.clone();
+    let ctor_name = mangle_struct_name(ctor_src_name.clone());
+    let export = export_callable(&flags, &ctor_name, &ctor_src_name);
+    let (ctor_args, ctor_names) = (&ctor_params.conversions, &ctor_params.names);
+    let (step_args, step_names) = (&step_params.conversions, &step_params.names);
+    let construct = wrap_return_value(
+        ctor_ret_t,
+        quote! { <#self_ty>::#ctor_src_name(#( #ctor_names ),*) },
+    );
+    let call_step = wrap_return_value(step_ret_t, quote! { self.step(#( #step_names ),*) });
+    let ret_value_t = returned_value_type(step_ret_t);
+    let checkpoint = if flags.checkpoint {
+        quote! {
+            fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+                encode_state(self).map(Some)
+            }
+
+            fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+                *self = decode_state(state)?;
+                Ok(())
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let arg_types: Vec<_> = ctor_params.types.iter().chain(&step_params.types).collect();
+    let params: Vec<_> = ctor_params
+        .params
+        .iter()
+        .chain(&step_params.params)
+        .collect();
+    let (arity, rest) = arity_of(arg_types.len(), &step_params.rest);
+    let signature = quote! {
+        Signature {
+            args: vec![#( <#arg_types as HasKind>::KIND ),*],
+            rest: #rest,
+            params: vec![#( #params ),*],
+            returns: <#ret_value_t as HasKind>::KIND,
+        }
+    };
+
+    quote! {
+        #[allow(dead_code)]
+        #block
+
+        impl Callable for #self_ty {
+            #[inline]
+            fn call(&mut self, args: &[Type]) -> CallResult {
+                #( #step_args )*
+                #call_step
+            }
+
+            #[inline(always)]
+            fn name(&self) -> &'static str {
+                stringify!(#ctor_src_name)
+            }
+
+            #[inline(always)]
+            fn arity(&self) -> Arity {
+                #arity
+            }
+
+            fn signature(&self) -> Signature {
+                #signature
+            }
+
+            #checkpoint
+        }
+
+        #[allow(non_camel_case_types)]
+        pub struct #ctor_name;
+
+        impl Callable for #ctor_name {
+            fn call(&mut self, args: &[Type]) -> CallResult {
+                // NOTE: arguments known only at runtime are None at build time
+                if args[..#argcnt].iter().any(|arg| matches!(arg, Type::None)) {
+                    return Err(CallError::new(
+                        self.name(),
+                        "leading arguments must be known at build time",
+                    ));
+                }
+                #( #ctor_args )*
+                #construct
+            }
+
+            #[inline(always)]
+            fn name(&self) -> &'static str {
+                stringify!(#ctor_src_name)
+            }
+
+            #[inline(always)]
+            fn arity(&self) -> Arity {
+                #arity
+            }
+
+            #[inline(always)]
+            fn of_type(&self) -> CallableType {
+                CallableType::Const
+            }
+
+            fn signature(&self) -> Signature {
+                Signature {
+                    returns: <#self_ty as HasKind>::KIND,
+                    ..#signature
+                }
+            }
         }
+
+        #export
     }
     .into()
 }
 
+/// Submits the callable into the link-time registry
+fn export_callable(flags: &Flags, fn_name: &syn::Ident, fn_src_name: &syn::Ident) -> TStream {
+    if !flags.export {
+        return quote! {};
+    }
+    quote! {
+        const _: () = {
+            fn make() -> Box<dyn Callable> {
+                Box::new(#fn_name)
+            }
+            inventory::submit! {
+                ExportedCallable {
+                    module: module_path!(),
+                    name: stringify!(#fn_src_name),
+                    make,
+                }
+            }
+        };
+    }
+}
+
 /// Resolves function name
 #[proc_macro]
 pub fn resolve_name(item: TokenStream) -> TokenStream {
@@ -206,14 +710,28 @@ pub fn resolve_name(item: TokenStream) -> TokenStream {
 /// Registers functions and constants in the given interpreter context;
 /// Macro uses a custom syntax to expose constants and functions.
 /// ## Syntax
-/// Macro consists of 4 sections in total with __constants__ and __functions__
+/// Macro consists of 5 sections in total with __constants__ and __functions__
 /// being mutualy optional.
 /// (they can be used both at once, only one of them but never without any of them)
 /// : __context__ -- is a section where user passes a __Context__ object.
 /// : __library__ -- this declares a common library root which is used in later sections.
+/// : __prefix__ -- optional string prepended to every registered name.
 /// : __constants__ -- registers constants.
 /// : __functions__ -- registers functions.
 ///
+/// Items are registered under their path relative to the library root,
+/// so formulas call them as `math::sin(x)`. Items listed with `use` are
+/// registered under their own name as well: `sin(x)`. An item followed by
+/// `as alias` is registered under the alias instead of its own name.
+/// Registering the same name twice in a section is a compile error.
+///
+/// Exported callables of a whole module are registered with `path::*;` under
+/// the path of the module they are defined in: `math::*;` registers
+/// `math::common::max`. An alias replaces the listed module path, so
+/// `math::* as m;` registers `m::common::max`, and `use math::*;` registers
+/// `max` as well. A name that already has another overload of the same
+/// signature makes registration panic.
+///
 /// ```ignore
 /// use some_xpr_lib;
 ///
@@ -223,13 +741,15 @@ pub fn resolve_name(item: TokenStream) -> TokenStream {
 ///     use_library! {
 ///         context ctx;
 ///         library some_xpr_lib::subcrate;
+///         prefix "xpr_";
 ///         constatns {
 ///             path::const::math::FOO;
-///             path::const::lib::BAR;
+///             use path::const::lib::BAR;
 ///         }
 ///         functions {
 ///             other::path::book;
-///             other::path::math::sin;
+///             use other::path::math::{sin, cos};
+///             other::path::math::tan as tangent;
 ///         }
 ///     };
 ///     // ...other library regestration...
@@ -237,7 +757,8 @@ pub fn resolve_name(item: TokenStream) -> TokenStream {
 ///     // ...
 /// }
 /// ```
-/// For example, the resulting path of constant `FOO` is `some_xpr_lib::subcrate::path::const::math::FOO`.
+/// For example, the resulting path of constant `FOO` is `some_xpr_lib::subcrate::path::const::math::FOO`
+/// and it is registered as `xpr_path::const::math::FOO`. Function `tan` is registered as `xpr_tangent`.
 #[proc_macro]
 pub fn use_library(item: TokenStream) -> TokenStream {
     // let parsed = parse_macro_input!();
@@ -248,35 +769,44 @@ pub fn use_library(item: TokenStream) -> TokenStream {
         .constants
         .values
         .into_iter()
-        .map(|(name, path, target)| {
-            // quote! { #ctx.register_constant(#name, #root #(::#path)?::#target); }
-            if !path.path.segments.is_empty() {
-                let mut full_path = root.clone();
-                full_path.path.segments.extend(path.path.segments);
-                quote! { #ctx.register_constant(#name, #full_path::#target); }
-            } else {
-                quote! { #ctx.register_constant(#name, #root::#target); }
-            }
+        .map(|entry| {
+            let (names, module, target) = (entry.names, entry.module, entry.target);
+            let item = quote! { #root #(::#module)* ::#target };
+            quote! { #( #ctx.register_constant(#names, ::core::convert::Into::into(#item)); )* }
         })
         .collect();
     let reg_function: Vec<_> = lib
         .functions
         .values
         .into_iter()
-        .map(|(name, path, target)| {
+        .map(|entry| {
+            let (names, module, import) = (entry.names, entry.module, entry.import);
+            let Some(target) = entry.target else {
+                // the whole module is picked up from the registry
+                let path = root
+                    .path
+                    .segments
+                    .iter()
+                    .map(|s| &s.ident)
+                    .chain(module.iter())
+                    .map(|ident| ident.to_string())
+                    .collect::<Vec<_>>()
+                    .join("::");
+                let import =
+                    import.map(|prefix| quote! { #ctx.import_module(#path, #prefix).unwrap(); });
+                return quote! {
+                    #( #ctx.register_module_as(#path, #names).unwrap(); )*
+                    #import
+                };
+            };
             let trgt = mangle_struct_name(target);
-            if !path.path.segments.is_empty() {
-                let mut full_path = root.clone();
-                full_path.path.segments.extend(path.path.segments);
-                quote! { #ctx.register_function(#name, Box::new(#full_path::#trgt)); }
-            } else {
-                quote! { #ctx.register_function(#name, Box::new(#root::#trgt)); }
-            }
+            let item = quote! { #root #(::#module)* ::#trgt };
+            quote! { #( #ctx.register_function(#names, Box::new(#item)); )* }
         })
         .collect();
     quote! {
-        #(#reg_constant);*
-        #(#reg_function);*
+        #(#reg_constant)*
+        #(#reg_function)*
     }
     .into()
 }
@@ -1,3 +1,4 @@
+use proc_macro2::Span;
 use syn::{self, parse::Parse, spanned::Spanned, Token};
 
 pub struct Library {
@@ -8,7 +9,156 @@ pub struct Library {
 }
 
 pub struct Paths {
-    pub values: Vec<(String, syn::ExprPath, syn::Ident)>,
+    pub values: Vec<Entry>,
+}
+
+/// Item of a library along with its module path relative to the library root
+pub struct Entry {
+    pub module: Vec<syn::Ident>,
+    /// Item of the module or all of its exported callables if there is none
+    pub target: Option<syn::Ident>,
+    /// Names the item is registered under. They are prefixes of the names
+    /// of callables if the entry stands for a whole module.
+    pub names: Vec<String>,
+    /// Prefix of the own names of callables of a whole module imported with `use`
+    pub import: Option<String>,
+}
+
+fn join_idents<'i>(idents: impl Iterator<Item = &'i syn::Ident>) -> String {
+    idents
+        .map(|ident| ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// Collects entries of a block and makes sure that each name is registered once
+struct Collector<'p> {
+    prefix: &'p str,
+    /// Whole modules may be registered only in the functions block
+    globs: bool,
+    values: Vec<Entry>,
+}
+
+impl Collector<'_> {
+    /// Adds an item registered under its qualified name, an `alias`
+    /// and its own name if it is `imported`
+    fn push(
+        &mut self,
+        mut module: Vec<syn::Ident>,
+        alias: Option<String>,
+        imported: bool,
+        span: Span,
+    ) -> syn::Result<()> {
+        let target = module.pop().unwrap();
+        let qualified = join_idents(module.iter().chain(std::iter::once(&target)));
+        let names = match (alias, imported) {
+            (Some(alias), false) => vec![alias],
+            (Some(alias), true) => vec![qualified, alias],
+            (None, true) => vec![qualified, target.to_string()],
+            (None, false) => vec![qualified],
+        };
+        let mut registered = Vec::with_capacity(names.len());
+        for name in names {
+            let name = format!("{}{}", self.prefix, name);
+            if registered.contains(&name) {
+                continue;
+            }
+            if self.values.iter().any(|entry| entry.names.contains(&name)) {
+                return Err(syn::Error::new(
+                    span,
+                    format!("Name '{}' is registered more than once", name),
+                ));
+            }
+            registered.push(name);
+        }
+        self.values.push(Entry {
+            module,
+            target: Some(target),
+            names: registered,
+            import: None,
+        });
+        Ok(())
+    }
+
+    /// Adds exported callables of a module. They are registered under their
+    /// module path with the module itself replaced by the `alias` namespace,
+    /// and under their own names if they are `imported`
+    fn push_glob(
+        &mut self,
+        module: Vec<syn::Ident>,
+        alias: Option<String>,
+        imported: bool,
+        span: Span,
+    ) -> syn::Result<()> {
+        if !self.globs {
+            return Err(syn::Error::new(
+                span,
+                "Only exported callables can be registered by a whole module".to_string(),
+            ));
+        }
+        let namespace = alias.unwrap_or_else(|| join_idents(module.iter()));
+        let names = if namespace.is_empty() {
+            vec![self.prefix.to_string()]
+        } else {
+            vec![format!("{}{}::", self.prefix, namespace)]
+        };
+        self.values.push(Entry {
+            module,
+            target: None,
+            names,
+            import: imported.then(|| self.prefix.to_string()),
+        });
+        Ok(())
+    }
+
+    /// Flattens `use a::{b, c::d as e};` into entries
+    fn push_use_tree(
+        &mut self,
+        tree: &syn::UseTree,
+        module: &mut Vec<syn::Ident>,
+    ) -> syn::Result<()> {
+        match tree {
+            syn::UseTree::Path(path) => {
+                module.push(path.ident.clone());
+                self.push_use_tree(&path.tree, module)?;
+                module.pop();
+            }
+            syn::UseTree::Name(name) => {
+                let mut path = module.clone();
+                path.push(name.ident.clone());
+                self.push(path, None, true, tree.span())?;
+            }
+            syn::UseTree::Rename(rename) => {
+                let mut path = module.clone();
+                path.push(rename.ident.clone());
+                self.push(path, Some(rename.rename.to_string()), true, tree.span())?;
+            }
+            syn::UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.push_use_tree(tree, module)?;
+                }
+            }
+            syn::UseTree::Glob(_) => self.push_glob(module.clone(), None, true, tree.span())?,
+        }
+        Ok(())
+    }
+}
+
+/// Parses a plain path like `math::max`. Returns `true` along with
+/// the module path if it ends with `::*`
+fn parse_path(input: syn::parse::ParseStream, glob: bool) -> syn::Result<(Vec<syn::Ident>, bool)> {
+    let mut idents = vec![];
+    loop {
+        if glob && !idents.is_empty() && input.peek(Token![*]) {
+            input.parse::<Token![*]>()?;
+            return Ok((idents, true));
+        }
+        idents.push(input.parse::<syn::Ident>()?);
+        if !input.peek(Token![::]) {
+            return Ok((idents, false));
+        }
+        input.parse::<Token![::]>()?;
+    }
 }
 
 fn eat_token(input: syn::parse::ParseStream, name: &str) -> syn::Result<()> {
@@ -22,7 +172,34 @@ fn eat_token(input: syn::parse::ParseStream, name: &str) -> syn::Result<()> {
     Ok(())
 }
 
-fn parse_path_block(input: syn::parse::ParseStream, name: &str) -> syn::Result<Paths> {
+/// Parses optional `prefix "ta_";` section
+fn parse_prefix(input: syn::parse::ParseStream) -> syn::Result<String> {
+    let fork = input.fork();
+    match fork.parse::<syn::Ident>() {
+        Ok(tok) if tok == "prefix" => {}
+        _ => return Ok(String::new()),
+    }
+    eat_token(input, "prefix")?;
+    let prefix = input.parse::<syn::LitStr>()?;
+    input.parse::<Token![;]>()?;
+    let valid = prefix
+        .value()
+        .split("::")
+        .all(|part| part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+    if !valid {
+        return Err(syn::Error::new(
+            prefix.span(),
+            "Prefix may contain only alphanumeric characters, '_' and '::'".to_string(),
+        ));
+    }
+    Ok(prefix.value())
+}
+
+fn parse_path_block(
+    input: syn::parse::ParseStream,
+    name: &str,
+    prefix: &str,
+) -> syn::Result<Paths> {
     let tok: Option<syn::Ident> = input.parse()?;
     if let Some(tok) = tok {
         if tok != name {
@@ -34,21 +211,43 @@ fn parse_path_block(input: syn::parse::ParseStream, name: &str) -> syn::Result<P
     } else {
         return Ok(Paths { values: vec![] });
     }
-    let block = input.parse::<syn::Block>()?;
-    let mut values = Vec::with_capacity(block.stmts.len());
-    for stmt in block.stmts {
-        if let syn::Stmt::Semi(syn::Expr::Path(mut value), _) = stmt {
-            let (last, _) = value.path.segments.pop().unwrap().into_tuple();
-            let name = last.ident.to_string();
-            values.push((name, value, last.ident));
+    let block;
+    syn::braced!(block in input);
+    let mut collector = Collector {
+        prefix,
+        globs: name == "functions",
+        values: Vec::new(),
+    };
+    while !block.is_empty() {
+        let span = block.span();
+        if block.peek(Token![use]) {
+            let item = block.parse::<syn::ItemUse>()?;
+            collector.push_use_tree(&item.tree, &mut Vec::new())?;
+            continue;
+        }
+        // `path::item;`, `path::item as alias;` or `path::*;`
+        let (path, glob) = parse_path(&block, true)?;
+        let alias = if block.peek(Token![as]) {
+            block.parse::<Token![as]>()?;
+            Some(join_idents(parse_path(&block, false)?.0.iter()))
         } else {
-            return Err(syn::Error::new(
-                stmt.span(),
-                format!("Recieved unrecognized path. Try adding semicolon ';'"),
-            ));
+            None
+        };
+        block.parse::<Token![;]>().map_err(|e| {
+            syn::Error::new(
+                e.span(),
+                "Recieved unrecognized path. Try adding semicolon ';'".to_string(),
+            )
+        })?;
+        if glob {
+            collector.push_glob(path, alias, false, span)?;
+        } else {
+            collector.push(path, alias, false, span)?;
         }
     }
-    Ok(Paths { values })
+    Ok(Paths {
+        values: collector.values,
+    })
 }
 
 impl Parse for Library {
@@ -59,8 +258,9 @@ impl Parse for Library {
         eat_token(input, "library")?;
         let root = input.parse::<syn::ExprPath>()?;
         input.parse::<Token![;]>()?;
-        let constants: Paths = parse_path_block(input, "constants")?;
-        let functions: Paths = parse_path_block(input, "functions")?;
+        let prefix = parse_prefix(input)?;
+        let constants: Paths = parse_path_block(input, "constants", &prefix)?;
+        let functions: Paths = parse_path_block(input, "functions", &prefix)?;
 
         Ok(Library {
             ctx,
@@ -1,12 +1,12 @@
 #![allow(dead_code)]
 extern crate xmacro;
-use types::{Callable, CallableType, Type};
+use serde::{Deserialize, Serialize};
+use types::{
+    decode_state, encode_state, Arity, CallError, CallResult, Callable, CallableType, HasKind,
+    Kind, Param, Signature, Type,
+};
 use xmacro::{resolve_name, runtime_callable};
 
-mod express {
-    pub use types;
-}
-
 #[runtime_callable(pure)]
 fn foo(input: f64) -> f64 {
     input * 2.0 + 2.0
@@ -17,6 +17,27 @@ fn bar(input: f64) -> f64 {
     input * 2.0 + 2.0
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Counter {
+    count: usize,
+    step: usize,
+}
+
+#[runtime_callable(stateful, checkpoint)]
+impl Counter {
+    fn ticks(start: usize, #[default = 1] step: usize) -> Self {
+        Counter { count: start, step }
+    }
+
+    fn step(&mut self, #[variadic] values: Vec<f64>) -> Option<usize> {
+        if values.is_empty() {
+            return None;
+        }
+        self.count += self.step * values.len();
+        Some(self.count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -29,4 +50,187 @@ mod tests {
         }
         let _ = resolve_name!(upper);
     }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            resolve_name!(foo).signature(),
+            Signature {
+                args: vec![Kind::Number],
+                rest: None,
+                params: vec![Param {
+                    name: "input",
+                    default: None
+                }],
+                returns: Kind::Number
+            }
+        );
+
+        #[runtime_callable]
+        fn repeat(input: String, _any: &Type, times: usize) -> Option<String> {
+            Some(input.repeat(times))
+        }
+        assert_eq!(
+            resolve_name!(repeat).signature(),
+            Signature {
+                args: vec![Kind::String, Kind::Any, Kind::Number],
+                rest: None,
+                params: vec![
+                    Param {
+                        name: "input",
+                        default: None
+                    },
+                    Param {
+                        name: "_any",
+                        default: None
+                    },
+                    Param {
+                        name: "times",
+                        default: None
+                    },
+                ],
+                returns: Kind::String
+            }
+        );
+    }
+
+    #[test]
+    fn test_default_params() {
+        #[runtime_callable(pure)]
+        fn pad(
+            input: String,
+            #[default = 2] times: usize,
+            #[default = "-"] fill: String,
+        ) -> String {
+            format!("{}{}", fill.repeat(times), input)
+        }
+        let params = resolve_name!(pad).signature().params;
+        assert_eq!(
+            params.iter().map(|p| p.name).collect::<Vec<_>>(),
+            vec!["input", "times", "fill"]
+        );
+        assert_eq!(params[0].default, None);
+        assert_eq!(params[1].default, Some(Type::Number(2.0)));
+        assert_eq!(params[2].default, Some(Type::String("-".to_string())));
+        assert_eq!(pad("a".to_string(), 1, "+".to_string()), "+a");
+    }
+
+    #[test]
+    fn test_variadic_expansion() {
+        #[runtime_callable(pure)]
+        fn sum(first: f64, #[variadic] rest: Vec<f64>) -> f64 {
+            rest.into_iter().fold(first, |acc, v| acc + v)
+        }
+        let mut f = resolve_name!(sum);
+        assert_eq!(f.arity(), Arity::variadic(1));
+        assert_eq!(f.signature().rest, Some(Kind::Number));
+        assert_eq!(f.call(&[Type::Number(1.0)]), Ok(Some(Type::Number(1.0))));
+        assert_eq!(
+            f.call(&[1.0.into(), 2.0.into(), 3.0.into()]),
+            Ok(Some(Type::Number(6.0)))
+        );
+
+        #[runtime_callable(pure)]
+        fn count(args: &[Type]) -> usize {
+            args.len()
+        }
+        let mut f = resolve_name!(count);
+        assert_eq!(f.arity(), Arity::variadic(0));
+        assert_eq!(f.signature().rest, Some(Kind::Any));
+        assert_eq!(
+            f.call(&[Type::None, Type::String("a".to_string())]),
+            Ok(Some(Type::Number(2.0)))
+        );
+
+        // without the attribute a trailing `Vec` takes a single array
+        #[runtime_callable(pure)]
+        fn scaled(x: f64, weights: Vec<f64>) -> f64 {
+            weights.into_iter().map(|w| w * x).sum()
+        }
+        let mut f = resolve_name!(scaled);
+        assert_eq!(f.arity(), Arity::exact(2));
+        assert_eq!(f.signature().rest, None);
+        assert_eq!(
+            f.call(&[2.0.into(), vec![1.0, 2.0].into()]),
+            Ok(Some(Type::Number(6.0)))
+        );
+    }
+
+    #[test]
+    fn test_fallible_expansion() {
+        #[runtime_callable(pure)]
+        fn checked_sqrt(input: f64) -> Result<f64, String> {
+            if input < 0.0 {
+                return Err(format!("negative input {}", input));
+            }
+            Ok(input.sqrt())
+        }
+        let mut f = resolve_name!(checked_sqrt);
+        assert_eq!(f.call(&[Type::Number(4.0)]), Ok(Some(Type::Number(2.0))));
+        let err = f.call(&[Type::Number(-1.0)]).unwrap_err();
+        assert_eq!(err.func, "checked_sqrt");
+        assert_eq!(err.reason, "negative input -1");
+    }
+
+    #[test]
+    fn test_stateful_expansion() {
+        let mut ctor = resolve_name!(ticks);
+        assert_eq!(ctor.name(), "ticks");
+        assert_eq!(ctor.arity(), Arity::variadic(2));
+        assert!(matches!(ctor.of_type(), CallableType::Const));
+        let signature = ctor.signature();
+        assert_eq!(signature.args, vec![Kind::Number, Kind::Number]);
+        assert_eq!(signature.rest, Some(Kind::Number));
+        assert_eq!(signature.params[1].default, Some(Type::Number(1.0)));
+        assert_eq!(signature.returns, Kind::Function);
+
+        // arguments known only at runtime are None at build time
+        let args = [Type::Number(10.0), Type::Number(2.0), Type::None];
+        let Ok(Some(Type::Function(state))) = ctor.call(&args) else {
+            panic!("constructor must return the state");
+        };
+        assert_eq!(state.name(), "ticks");
+        assert_eq!(state.signature().returns, Kind::Number);
+        let args = [Type::Number(10.0), Type::Number(2.0), Type::Number(0.5)];
+        assert_eq!(state.call(&args), Ok(Some(Type::Number(12.0))));
+        assert_eq!(state.call(&args), Ok(Some(Type::Number(14.0))));
+        assert_eq!(state.call(&args[..2]), Ok(None));
+
+        let err = ctor.call(&[Type::None, Type::Number(2.0)]).unwrap_err();
+        assert_eq!(err.func, "ticks");
+        assert_eq!(err.reason, "leading arguments must be known at build time");
+    }
+
+    #[test]
+    fn test_stateful_checkpoint() {
+        let mut ctor = resolve_name!(ticks);
+        let Ok(Some(Type::Function(state))) = ctor.call(&[Type::Number(0.0), Type::Number(1.0)])
+        else {
+            panic!("constructor must return the state");
+        };
+        let args = [Type::Number(0.0), Type::Number(1.0), Type::Number(0.5)];
+        assert_eq!(state.call(&args), Ok(Some(Type::Number(1.0))));
+        let saved = state.save_state().unwrap().expect("state must be saved");
+        assert_eq!(state.call(&args), Ok(Some(Type::Number(2.0))));
+        state.load_state(&saved).unwrap();
+        assert_eq!(state.call(&args), Ok(Some(Type::Number(2.0))));
+        assert!(state.load_state(&[]).is_err());
+
+        // plain stateful functions keep nothing of their own
+        #[runtime_callable]
+        fn upper(input: String) -> Option<String> {
+            Some(input.to_uppercase())
+        }
+        let mut upper = resolve_name!(upper);
+        assert_eq!(upper.save_state(), Ok(Some(Vec::new())));
+        assert_eq!(upper.load_state(&[]), Ok(()));
+
+        // pure functions have nothing to save
+        let mut foo = resolve_name!(foo);
+        assert_eq!(foo.save_state(), Ok(None));
+        assert_eq!(
+            foo.load_state(&saved),
+            Err("foo has no state to restore".to_string())
+        );
+    }
 }
@@ -0,0 +1,488 @@
+//! Functions the DSL syntax is lowered into.
+//! They are not registered in the [Context](crate::ctx::Context),
+//! so user libraries can't shadow them.
+use crate::formula::Formula;
+use crate::ir::IRNode;
+use crate::typeck::pick_overload;
+use express::lang::ast::Expression;
+use express::prelude::*;
+use express::types::Field;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Builds an array from its elements: `[a, b, c]`
+#[runtime_callable(pure)]
+pub fn array(#[variadic] items: Vec<f64>) -> Arc<[f64]> {
+    items.into()
+}
+
+/// Returns an element of the array: `arr[i]`
+#[runtime_callable(pure)]
+pub fn index(arr: &[f64], i: f64) -> Result<f64, String> {
+    if i < 0.0 || i.fract() != 0.0 {
+        return Err(format!("index {} is not a valid array index", i));
+    }
+    arr.get(i as usize).copied().ok_or(format!(
+        "index {} is out of bounds for array of length {}",
+        i,
+        arr.len()
+    ))
+}
+
+/// Reads a field of a record value: `&last.price`.
+/// Created per field from the field table of the record [Kind].
+pub struct FieldAccess {
+    record: Kind,
+    field: &'static Field,
+}
+
+impl FieldAccess {
+    pub fn new(record: Kind, field: &'static Field) -> Self {
+        Self { record, field }
+    }
+}
+
+impl Callable for FieldAccess {
+    fn name(&self) -> &'static str {
+        self.field.name
+    }
+
+    fn call(&mut self, args: &[Type]) -> CallResult {
+        match (self.field.get)(&args[0]) {
+            Some(value) => Ok(Some(value)),
+            None => Err(CallError::new(
+                self.field.name,
+                format!("{} has no field '{}'", args[0].kind(), self.field.name),
+            )),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+
+    fn of_type(&self) -> CallableType {
+        CallableType::Pure
+    }
+
+    fn signature(&self) -> Signature {
+        Signature {
+            args: vec![self.record],
+            rest: None,
+            params: vec![Param {
+                name: "record",
+                default: None,
+            }],
+            returns: self.field.kind,
+        }
+    }
+}
+
+/// Arguments of the ongoing lambda call shared with its parameters
+pub type LambdaArgs = Rc<RefCell<Vec<Type>>>;
+
+/// Parameter of a lambda. Reads its argument of the ongoing call.
+/// It is never folded at build time since it is only lowered as
+/// an operand and never called with constant arguments.
+pub struct LambdaArg {
+    args: LambdaArgs,
+    pos: usize,
+    name: &'static str,
+}
+
+impl LambdaArg {
+    pub fn new(args: LambdaArgs, pos: usize, name: &'static str) -> Self {
+        Self { args, pos, name }
+    }
+}
+
+impl Callable for LambdaArg {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn call(&mut self, _: &[Type]) -> CallResult {
+        Ok(self.args.borrow().get(self.pos).cloned())
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+
+    fn of_type(&self) -> CallableType {
+        CallableType::Pure
+    }
+}
+
+/// Inline function: `|acc, x| acc + x`. It is passed around as
+/// a [Type::Function] value and evaluates its body on every call.
+/// Lambdas share the same name, they are told apart by their source,
+/// see [IRNode::to_expression].
+pub struct Lambda {
+    args: LambdaArgs,
+    params: Vec<String>,
+    body: Formula,
+    pure: bool,
+}
+
+impl Lambda {
+    pub fn new(args: LambdaArgs, params: Vec<String>, body: IRNode) -> Self {
+        Self {
+            args,
+            params,
+            pure: is_pure(&body),
+            body: Formula::from_ir("lambda", body),
+        }
+    }
+
+    /// Returns the lambda in the source syntax
+    pub fn to_expression(&self) -> Expression {
+        Expression::Lambda {
+            params: self.params.clone(),
+            body: Box::new(self.body.ast.to_expression()),
+        }
+    }
+}
+
+/// Tells if the node calls only pure functions
+fn is_pure(node: &IRNode) -> bool {
+    match node {
+        IRNode::Value(Type::Function(f)) => f.can_be_optimized(),
+        IRNode::Value(_) | IRNode::Ref(_) => true,
+        // parameters may be changed between ticks
+        IRNode::Param(_) => false,
+        IRNode::Function(f, args) => f.can_be_optimized() && args.iter().all(is_pure),
+        IRNode::BinOp(lhs, rhs, _) => is_pure(lhs) && is_pure(rhs),
+        IRNode::UnOp(rhs, _) => is_pure(rhs),
+    }
+}
+
+/// Collects functions of the node that keep a state in the order they
+/// appear. Functions passed to a stateful call are owned by it, so they
+/// are saved along with its state.
+#[cfg(feature = "checkpoint")]
+pub(crate) fn stateful_calls(node: &IRNode, calls: &mut Vec<Function>) {
+    match node {
+        IRNode::Value(Type::Function(f)) if !f.can_be_optimized() => calls.push(f.clone()),
+        IRNode::Value(_) | IRNode::Ref(_) | IRNode::Param(_) => {}
+        IRNode::Function(f, _) if !f.can_be_optimized() => calls.push(f.clone()),
+        IRNode::Function(_, args) => args.iter().for_each(|arg| stateful_calls(arg, calls)),
+        IRNode::BinOp(lhs, rhs, _) => {
+            stateful_calls(lhs, calls);
+            stateful_calls(rhs, calls);
+        }
+        IRNode::UnOp(rhs, _) => stateful_calls(rhs, calls),
+    }
+}
+
+impl Callable for Lambda {
+    fn name(&self) -> &'static str {
+        "lambda"
+    }
+
+    fn call(&mut self, args: &[Type]) -> CallResult {
+        self.args.replace(args.to_vec());
+        self.body.eval()
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(self.params.len())
+    }
+
+    fn of_type(&self) -> CallableType {
+        if self.pure {
+            CallableType::Pure
+        } else {
+            CallableType::Stateful
+        }
+    }
+
+    /// Saves states of the stateful calls in the body
+    #[cfg(feature = "checkpoint")]
+    fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+        let mut calls = Vec::new();
+        stateful_calls(&self.body.ast, &mut calls);
+        let mut states = Vec::with_capacity(calls.len());
+        for f in calls {
+            match f.save_state()? {
+                Some(state) => states.push(state),
+                None => return Ok(None),
+            }
+        }
+        encode_state(&states).map(Some)
+    }
+
+    #[cfg(feature = "checkpoint")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let states: Vec<Vec<u8>> = decode_state(state)?;
+        let mut calls = Vec::new();
+        stateful_calls(&self.body.ast, &mut calls);
+        if states.len() != calls.len() {
+            return Err(format!(
+                "function {} has {} stateful calls, but recieves {} states",
+                self.name(),
+                calls.len(),
+                states.len()
+            ));
+        }
+        calls
+            .iter()
+            .zip(&states)
+            .try_for_each(|(f, state)| f.load_state(state))
+    }
+}
+
+/// Lambda that captures parameters of the enclosing one. It is not
+/// a constant value, since it can only be called while the enclosing
+/// lambda is, so it is produced by a call instead.
+pub struct Closure {
+    lambda: Function,
+}
+
+impl Closure {
+    pub fn new(lambda: Function) -> Self {
+        Self { lambda }
+    }
+
+    pub fn lambda(&self) -> &Function {
+        &self.lambda
+    }
+}
+
+impl Callable for Closure {
+    fn name(&self) -> &'static str {
+        self.lambda.name()
+    }
+
+    fn call(&mut self, _: &[Type]) -> CallResult {
+        Ok(Some(Type::Function(self.lambda.clone())))
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+
+    fn of_type(&self) -> CallableType {
+        self.lambda.of_type()
+    }
+
+    fn signature(&self) -> Signature {
+        Signature {
+            args: Vec::new(),
+            rest: None,
+            params: Vec::new(),
+            returns: Kind::Function,
+        }
+    }
+
+    #[cfg(feature = "checkpoint")]
+    fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+        self.lambda.save_state()
+    }
+
+    #[cfg(feature = "checkpoint")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.lambda.load_state(state)
+    }
+}
+
+/// Constant whose value has no literal, like a record or a function.
+/// It is lowered into a call rather than a value, so the formula
+/// is printed with the name of the constant.
+pub struct Constant {
+    name: &'static str,
+    value: Type,
+}
+
+impl Constant {
+    pub fn new(name: &'static str, value: Type) -> Self {
+        Self { name, value }
+    }
+
+    pub fn value(&self) -> &Type {
+        &self.value
+    }
+}
+
+impl Callable for Constant {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn call(&mut self, _: &[Type]) -> CallResult {
+        Ok(Some(self.value.clone()))
+    }
+
+    fn call_shared(&self, _: &[Type]) -> Option<CallResult> {
+        Some(Ok(Some(self.value.clone())))
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+
+    fn of_type(&self) -> CallableType {
+        CallableType::Pure
+    }
+
+    fn signature(&self) -> Signature {
+        Signature {
+            args: Vec::new(),
+            rest: None,
+            params: Vec::new(),
+            returns: self.value.kind(),
+        }
+    }
+}
+
+/// Call of an overloaded function whose overload depends on kinds of
+/// referenced formulas. They are unknown while lowering, so the overload
+/// is picked by the [TypeChecker](crate::typeck::TypeChecker) once formulas
+/// are linked. Calls inside of lambdas are resolved by kinds of their
+/// arguments. Calls are dispatched to the picked overload, which is picked
+/// again once kinds of the arguments change.
+pub struct Overloaded {
+    /// Name the function is called by
+    call_name: String,
+    candidates: Vec<Function>,
+    /// Position of the picked candidate along with the function it stands for
+    resolved: RefCell<Option<(usize, Function)>>,
+    /// Kinds of the arguments the candidate is picked for
+    picked_for: RefCell<Vec<Kind>>,
+}
+
+impl Overloaded {
+    pub fn new(call_name: &str, candidates: Vec<Function>) -> Self {
+        Self {
+            call_name: call_name.to_string(),
+            candidates,
+            resolved: RefCell::new(None),
+            picked_for: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Picks the overload for the argument kinds and returns the kind of
+    /// its result. The state of a stateful one is constructed from constant
+    /// arguments right away. It is kept while the same overload is picked.
+    pub fn resolve(&self, kinds: &[Kind], args: &[IRNode]) -> Result<Kind, String> {
+        let pos = pick_overload(&self.call_name, &self.candidates, kinds)?;
+        self.picked_for.replace(kinds.to_vec());
+        let mut resolved = self.resolved.borrow_mut();
+        match &*resolved {
+            Some((picked, f)) if *picked == pos => Ok(f.signature().returns),
+            _ => {
+                let f = &self.candidates[pos];
+                let f = if f.is_const() {
+                    construct(f, args)?
+                } else {
+                    f.clone()
+                };
+                let returns = f.signature().returns;
+                *resolved = Some((pos, f));
+                Ok(returns)
+            }
+        }
+    }
+
+    fn dispatch(&self, args: &[Type]) -> CallResult {
+        let kinds: Vec<_> = args.iter().map(Type::kind).collect();
+        if self.resolved.borrow().is_none() || *self.picked_for.borrow() != kinds {
+            let values: Vec<_> = args.iter().cloned().map(IRNode::Value).collect();
+            self.resolve(&kinds, &values)
+                .map_err(|e| CallError::new(self.name(), e))?;
+        }
+        let resolved = self.resolved.borrow().as_ref().unwrap().1.clone();
+        resolved.call(args)
+    }
+}
+
+/// Constructs the state of a stateful function. Arguments known
+/// only at runtime are passed as `None`.
+fn construct(f: &Function, args: &[IRNode]) -> Result<Function, String> {
+    let values: Vec<_> = args
+        .iter()
+        .map(|arg| match arg {
+            IRNode::Value(t) => t.clone(),
+            _ => Type::None,
+        })
+        .collect();
+    match f.call(&values) {
+        Ok(Some(Type::Function(state))) => Ok(state),
+        Ok(_) => Err(format!("Function {} expects constant arguments", f.name())),
+        Err(e) => Err(format!(
+            "Failed to evaluate a call at build time. Reason: {}",
+            e
+        )),
+    }
+}
+
+impl Callable for Overloaded {
+    fn name(&self) -> &'static str {
+        self.candidates[0].name()
+    }
+
+    fn call(&mut self, args: &[Type]) -> CallResult {
+        self.dispatch(args)
+    }
+
+    fn call_shared(&self, args: &[Type]) -> Option<CallResult> {
+        Some(self.dispatch(args))
+    }
+
+    fn arity(&self) -> Arity {
+        self.candidates[0].arity()
+    }
+
+    fn of_type(&self) -> CallableType {
+        if self
+            .candidates
+            .iter()
+            .all(|f| f.of_type() == CallableType::Pure)
+        {
+            CallableType::Pure
+        } else {
+            CallableType::Stateful
+        }
+    }
+
+    fn signature(&self) -> Signature {
+        let returns = match &*self.resolved.borrow() {
+            Some((_, f)) => f.signature().returns,
+            None => {
+                let returns = self.candidates[0].signature().returns;
+                let common = self
+                    .candidates
+                    .iter()
+                    .all(|f| !f.is_const() && f.signature().returns == returns);
+                if common {
+                    returns
+                } else {
+                    Kind::Any
+                }
+            }
+        };
+        Signature {
+            returns,
+            ..Signature::any(self.arity())
+        }
+    }
+
+    /// Only a stateful overload has a state, the others save nothing
+    #[cfg(feature = "checkpoint")]
+    fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+        match &*self.resolved.borrow() {
+            Some((_, f)) if !f.can_be_optimized() => f.save_state(),
+            _ => Ok(Some(Vec::new())),
+        }
+    }
+
+    #[cfg(feature = "checkpoint")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        match &*self.resolved.borrow() {
+            Some((_, f)) if !f.can_be_optimized() => f.load_state(state),
+            _ if state.is_empty() => Ok(()),
+            _ => Err(format!("{} has no state to restore", self.name())),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use express::types::{decode_state, encode_state, TimeStep, Type};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// State of an interpreter that survives a restart.
+/// It is only valid for the formula set it was made for.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Hash of the formula set, see [hash_formulas]
+    pub formulas: u64,
+    /// Last results of formulas by their names
+    pub results: BTreeMap<String, Value>,
+    /// States of stateful functions by formula names and positions
+    /// of the calls in them
+    pub states: BTreeMap<String, Vec<u8>>,
+}
+
+impl Checkpoint {
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        encode_state(self).map_err(|e| format!("Failed to encode checkpoint. Reason: {}", e))
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        decode_state(bytes).map_err(|e| format!("Failed to decode checkpoint. Reason: {}", e))
+    }
+}
+
+/// Serializable mirror of [Type]. Functions have no counterpart,
+/// so their results are not saved.
+#[derive(Serialize, Deserialize)]
+pub enum Value {
+    Number(f64),
+    String(String),
+    Collection(Vec<(f64, f64)>),
+    TimeStep(f64, f64),
+    Array(Vec<f64>),
+}
+
+impl Value {
+    pub fn from_type(val: &Type) -> Option<Self> {
+        match val {
+            Type::Number(num) => Some(Value::Number(*num)),
+            Type::String(string) => Some(Value::String(string.clone())),
+            Type::Collection(steps) => Some(Value::Collection(
+                steps.iter().map(|ts| (ts.price, ts.time)).collect(),
+            )),
+            Type::TimeStep(ts) => Some(Value::TimeStep(ts.price, ts.time)),
+            Type::Array(items) => Some(Value::Array(items.to_vec())),
+            Type::Function(_) | Type::None => None,
+        }
+    }
+}
+
+impl From<Value> for Type {
+    fn from(val: Value) -> Self {
+        match val {
+            Value::Number(num) => Type::Number(num),
+            Value::String(string) => Type::String(string),
+            Value::Collection(steps) => Type::Collection(
+                steps
+                    .into_iter()
+                    .map(|(price, time)| TimeStep { price, time })
+                    .collect(),
+            ),
+            Value::TimeStep(price, time) => Type::TimeStep(TimeStep { price, time }),
+            Value::Array(items) => Type::Array(items.into()),
+        }
+    }
+}
+
+/// Hashes printed formulas by their names. Unlike `DefaultHasher`
+/// FNV-1a is stable across builds, so a checkpoint can be restored
+/// by a newer version of the program.
+pub fn hash_formulas<'f>(formulas: impl Iterator<Item = (&'f str, String)>) -> u64 {
+    let sorted: BTreeMap<_, _> = formulas.collect();
+    let mut hash = 0xcbf29ce484222325u64;
+    for (name, expr) in sorted {
+        for byte in name
+            .bytes()
+            .chain([b'='])
+            .chain(expr.bytes())
+            .chain([b'\n'])
+        {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
@@ -1,18 +1,56 @@
-use crate::ir::{FormulaLink, IRNode};
+use crate::builtin;
+use crate::ir::{FormulaLink, IRNode, ParamSlot};
+use crate::typeck::{best_overloads, check_call, check_operands, kind_of, overload_error};
 use express::{
-    lang::ast::{Expression, Literal, Visit},
-    types::{Callable, Function, InterpreterContext, Type},
+    lang::ast::{Definition, Expression, Literal, TimeUnit, Visit},
+    lang::operators::{Fixity, OpKind, Operator, OperatorTable},
+    lang::parser::parse_definitions_with,
+    types::{
+        Arity, CallError, CallResult, Callable, CallableType, Function, InterpreterContext, Kind,
+        Param, Signature, Type,
+    },
 };
-use std::collections::BTreeMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+use std::sync::Mutex;
 
 type Namespace<T> = BTreeMap<String, T>;
 
+/// A set of functions registered under the same name.
+/// They are told apart by their argument count and kinds.
+pub type Overloads = Vec<Function>;
+
 /// Holds evaluation context information such as functions
 /// that implement `Callable` trait and named constants.
+/// Names of functions and constants may be qualified by namespaces
+/// like `ta::ema` or `math::PI`. Members of a namespace share its prefix.
 pub struct Context {
-    pub ns_fn: Namespace<Function>,
-    pub ns_const: Namespace<f64>,
+    pub ns_fn: Namespace<Overloads>,
+    pub ns_const: Namespace<Type>,
+    /// Parameters like `$threshold` that may be changed between ticks
+    pub ns_param: Namespace<ParamSlot>,
     pub tmp_obj_lookup: BTreeMap<(String, Vec<Expression>), Function>,
+    /// Functions defined in the DSL along with their registered objects.
+    /// Definitions of the same name differ in the number of parameters.
+    pub ns_def: Namespace<Vec<(Function, Definition)>>,
+    /// Reports unresolved identifiers as errors. Otherwise they are
+    /// treated as strings for compatibility with unquoted string arguments.
+    pub strict: bool,
+    /// Unit of `TimeStep::time` in the data feed. Duration literals
+    /// like `5m` are converted into it at build time.
+    pub time_unit: TimeUnit,
+    /// Operators recognized in formulas and definitions
+    pub operators: OperatorTable,
+    /// Let-bindings visible at the current point of lowering
+    scopes: RefCell<Vec<Namespace<IRNode>>>,
+    /// Let-bound values lowered into hidden formulas
+    locals: RefCell<Vec<(String, IRNode)>>,
+    local_cnt: Cell<usize>,
+    /// Parameters of lambdas enclosing the current point of lowering
+    lambda_params: RefCell<Vec<Function>>,
+    /// Position of the outermost lambda parameter used by the lowered body
+    lambda_captures: Cell<usize>,
 }
 
 impl Context {
@@ -21,55 +59,717 @@ impl Context {
         Self {
             ns_fn: Namespace::new(),
             ns_const: Namespace::new(),
+            ns_param: Namespace::new(),
             tmp_obj_lookup: BTreeMap::new(),
+            ns_def: Namespace::new(),
+            strict: false,
+            time_unit: TimeUnit::Milliseconds,
+            operators: OperatorTable::default(),
+            scopes: RefCell::new(Vec::new()),
+            locals: RefCell::new(Vec::new()),
+            local_cnt: Cell::new(0),
+            lambda_params: RefCell::new(Vec::new()),
+            lambda_captures: Cell::new(usize::MAX),
+        }
+    }
+
+    /// Takes hidden formulas produced by let-bindings of the last lowered expressions.
+    /// Their values are computed once per pass and shared through references.
+    pub fn take_locals(&self) -> Vec<(String, IRNode)> {
+        self.locals.take()
+    }
+
+    /// Marks the state of lowering that [Context::rollback] returns to
+    pub(crate) fn mark(&self) -> usize {
+        self.local_cnt.get()
+    }
+
+    /// Forgets names of hidden formulas given out since the `mark`,
+    /// so that a formula that failed to build doesn't shift them
+    pub(crate) fn rollback(&self, mark: usize) {
+        self.locals.take();
+        self.local_cnt.set(mark);
+    }
+
+    fn find_local(&self, name: &str) -> Option<IRNode> {
+        self.scopes
+            .borrow()
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+    }
+
+    /// Binds a name within the body. Constants and references are inlined,
+    /// other values are moved into a hidden formula so that the body
+    /// refers to a single computed value instead of duplicating it.
+    fn visit_let(
+        &self,
+        name: String,
+        value: Expression,
+        body: Expression,
+    ) -> Result<IRNode, String> {
+        let mark = self.locals.borrow().len();
+        let result = self
+            .visit_expr(value)
+            .and_then(|value| self.lower_scoped(vec![(name, value)], false, body));
+        if result.is_err() {
+            self.locals.borrow_mut().truncate(mark);
+        }
+        result
+    }
+
+    /// Lowers the body with given names bound to lowered values.
+    /// An `isolated` body doesn't see names bound outside of it.
+    fn lower_scoped(
+        &self,
+        bindings: Vec<(String, IRNode)>,
+        isolated: bool,
+        body: Expression,
+    ) -> Result<IRNode, String> {
+        let mark = self.locals.borrow().len();
+        let mut scope = Namespace::new();
+        let mut hidden = Vec::new();
+        for (name, value) in bindings {
+            let binding = match value {
+                IRNode::Value(_) | IRNode::Ref(_) | IRNode::Param(_) => value,
+                // lambda bodies are evaluated apart from the graph
+                _ if !self.lambda_params.borrow().is_empty() => value,
+                _ => {
+                    let cnt = self.local_cnt.get();
+                    self.local_cnt.set(cnt + 1);
+                    let local = format!("__let_{}_{}", name, cnt);
+                    self.locals.borrow_mut().push((local.clone(), value));
+                    hidden.push(local.clone());
+                    IRNode::Ref(FormulaLink::new(&local))
+                }
+            };
+            scope.insert(name, binding);
+        }
+        let outer = if isolated {
+            self.scopes.take()
+        } else {
+            Vec::new()
+        };
+        self.scopes.borrow_mut().push(scope);
+        let body = self.visit_expr(body);
+        if isolated {
+            self.scopes.replace(outer);
+        } else {
+            self.scopes.borrow_mut().pop();
+        }
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => {
+                self.locals.borrow_mut().truncate(mark);
+                return Err(e);
+            }
+        };
+
+        // drop bindings if neither the body nor inner bindings use them
+        let mut locals = self.locals.borrow_mut();
+        for local in hidden.iter().rev() {
+            let pos = locals.iter().position(|(n, _)| n == local).unwrap();
+            let used = body.references(local)
+                || locals[pos + 1..].iter().any(|(_, ir)| ir.references(local));
+            if !used {
+                locals.remove(pos);
+            }
+        }
+        Ok(body)
+    }
+
+    /// Lowers an inline function into a [Type::Function] value.
+    /// Its parameters read arguments of the ongoing call, while names
+    /// bound outside of the lambda are captured.
+    fn visit_lambda(&self, params: Vec<String>, body: Expression) -> Result<IRNode, String> {
+        for (pos, param) in params.iter().enumerate() {
+            if params[..pos].contains(param) {
+                return Err(format!("Lambda has duplicate parameter '{}'", param));
+            }
+        }
+        let args = builtin::LambdaArgs::default();
+        let slots: Vec<_> = params
+            .iter()
+            .enumerate()
+            .map(|(pos, param)| {
+                let arg = builtin::LambdaArg::new(args.clone(), pos, intern(param));
+                Function::from_callable(Box::new(arg))
+            })
+            .collect();
+        let scope = params
+            .iter()
+            .cloned()
+            .zip(slots.iter().map(|f| IRNode::Function(f.clone(), vec![])))
+            .collect();
+        let base = self.lambda_params.borrow().len();
+        self.lambda_params.borrow_mut().extend(slots);
+        let outer_captures = self.lambda_captures.replace(usize::MAX);
+        self.scopes.borrow_mut().push(scope);
+        let body = self.visit_expr(body);
+        self.scopes.borrow_mut().pop();
+        self.lambda_params.borrow_mut().truncate(base);
+        let captures = self.lambda_captures.get();
+        self.lambda_captures.set(captures.min(outer_captures));
+
+        let body = body?;
+        if let Some(name) = body.first_ref() {
+            return Err(format!(
+                "Lambda cannot refer to formula '{}'. Pass its value as an argument instead",
+                name
+            ));
+        }
+        let lambda = builtin::Lambda::new(args, params, body);
+        let lambda = Function::from_callable(Box::new(lambda));
+        if captures < base {
+            let closure = builtin::Closure::new(lambda);
+            return Ok(IRNode::Function(
+                Function::from_callable(Box::new(closure)),
+                vec![],
+            ));
+        }
+        Ok(IRNode::Value(Type::Function(lambda)))
+    }
+
+    /// Remembers that the lowered body uses a parameter of an enclosing lambda
+    fn note_capture(&self, local: &IRNode) {
+        if let IRNode::Function(f, _) = local {
+            let pos = self
+                .lambda_params
+                .borrow()
+                .iter()
+                .position(|p| Rc::ptr_eq(&p.clone_rc(), &f.clone_rc()));
+            if let Some(pos) = pos {
+                self.lambda_captures
+                    .set(self.lambda_captures.get().min(pos));
+            }
+        }
+    }
+
+    /// Registers functions defined in the DSL script like:
+    /// `def zscore(x, m, s) = (x - m) / s`.
+    /// A definition shadows overloads registered under the same name
+    /// that accept its number of arguments, variadic ones included.
+    /// Its calls are inlined, so constant arguments get folded at build time.
+    /// Constants like `const K = 2 * PI` are evaluated right away and may
+    /// be used by the following definitions.
+    pub fn define(&mut self, script: &str) -> Result<(), String> {
+        let definitions = parse_definitions_with(script, &self.operators)
+            .map_err(|e| format!("Failed to parse definitions. Reason: {}", e))?;
+        for definition in definitions {
+            self.register_definition(definition)?;
         }
+        Ok(())
+    }
+
+    /// Registers a parameter like `$threshold` with its default value.
+    /// Unlike constants parameters are not folded while lowering,
+    /// so they can be changed with `Interpreter::set_param` between ticks.
+    pub fn register_param(&mut self, name: &str, default: Type) {
+        self.ns_param
+            .insert(name.to_string(), ParamSlot::new(name, default));
+    }
+
+    /// Registers an operator that calls the function with its operands,
+    /// e.g. `a %% b` is parsed as `rem(a, b)` after
+    /// `register_operator("%%", Fixity::Infix(Assoc::Left), 20, "rem")`.
+    /// Builtin operators have precedences from 10 (`+`) to 40 (`**`).
+    pub fn register_operator(
+        &mut self,
+        symbol: &str,
+        fixity: Fixity,
+        precedence: u8,
+        function: &str,
+    ) -> Result<(), String> {
+        self.operators.add(Operator::new(
+            symbol,
+            fixity,
+            precedence,
+            OpKind::Call(function.to_string()),
+        ))
+    }
+
+    fn register_definition(&mut self, definition: Definition) -> Result<(), String> {
+        let name = definition.name().to_string();
+        let params = match &definition {
+            Definition::Function { params, .. } => params,
+            Definition::Const { body, .. } => {
+                if self.ns_const.contains_key(&name) {
+                    return Err(format!("Constant {} is already defined", name));
+                }
+                return match self.visit_expr(body.clone())? {
+                    IRNode::Value(value) => {
+                        self.ns_const.insert(name, value);
+                        Ok(())
+                    }
+                    // constants without a literal are lowered into calls
+                    IRNode::Function(f, args) if args.is_empty() => {
+                        match f.downcast_ref::<builtin::Constant>() {
+                            Some(cnst) => {
+                                self.ns_const.insert(name, cnst.value().clone());
+                                Ok(())
+                            }
+                            None => Err(format!("Constant {} must be known at build time", name)),
+                        }
+                    }
+                    _ => Err(format!("Constant {} must be known at build time", name)),
+                };
+            }
+        };
+        for (pos, param) in params.iter().enumerate() {
+            if params[..pos].contains(param) {
+                return Err(format!(
+                    "Function {} has duplicate parameter '{}'",
+                    name, param
+                ));
+            }
+        }
+        if self.reaches_definition(definition.body(), &name, &mut Vec::new()) {
+            return Err(format!(
+                "Function {} is recursive. Recursion is not supported",
+                name
+            ));
+        }
+        let func = Function::from_callable(Box::new(DslFunction::new(&name, params)));
+        // overloads accepting the same number of arguments are shadowed
+        let argcnt = params.len();
+        let overloads = self.ns_fn.entry(name.clone()).or_default();
+        overloads.retain(|f| !f.arity().accepts(argcnt));
+        overloads.push(func.clone());
+        let definitions = self.ns_def.entry(name).or_default();
+        definitions.retain(|(f, _)| !f.arity().accepts(argcnt));
+        definitions.push((func, definition));
+        Ok(())
+    }
+
+    /// Tells if the expression calls the definition `target` directly
+    /// or through other definitions.
+    fn reaches_definition(&self, expr: &Expression, target: &str, seen: &mut Vec<String>) -> bool {
+        match expr {
+            Expression::Const(_) => false,
+            Expression::Function { name, args } => {
+                if args
+                    .iter()
+                    .any(|arg| self.reaches_definition(arg, target, seen))
+                {
+                    return true;
+                }
+                let Literal::Ident(name) = name else {
+                    return false;
+                };
+                if name == target {
+                    return true;
+                }
+                if seen.contains(name) {
+                    return false;
+                }
+                seen.push(name.clone());
+                self.ns_def.get(name).is_some_and(|definitions| {
+                    definitions
+                        .iter()
+                        .any(|(_, def)| self.reaches_definition(def.body(), target, seen))
+                })
+            }
+            Expression::BinOp(lhs, rhs, _) => {
+                self.reaches_definition(lhs, target, seen)
+                    || self.reaches_definition(rhs, target, seen)
+            }
+            Expression::UnOp(_, rhs) | Expression::NamedArg(_, rhs) => {
+                self.reaches_definition(rhs, target, seen)
+            }
+            Expression::Array(items) => items
+                .iter()
+                .any(|item| self.reaches_definition(item, target, seen)),
+            Expression::Index(arr, idx) => {
+                self.reaches_definition(arr, target, seen)
+                    || self.reaches_definition(idx, target, seen)
+            }
+            Expression::Field(base, _) => self.reaches_definition(base, target, seen),
+            Expression::Lambda { body, .. } => self.reaches_definition(body, target, seen),
+            Expression::Let { value, body, .. } => {
+                self.reaches_definition(value, target, seen)
+                    || self.reaches_definition(body, target, seen)
+            }
+        }
+    }
+
+    /// Returns the DSL definition behind the function if there is one
+    fn find_definition(&self, f: &Function) -> Option<&Definition> {
+        self.ns_def
+            .get(f.name())?
+            .iter()
+            .find(|(func, _)| Rc::ptr_eq(&func.clone_rc(), &f.clone_rc()))
+            .map(|(_, def)| def)
     }
 }
 
+// NOTE(iy): Callable names are static. Names of definitions and constants
+// are interned, so redefining the same formulas doesn't allocate them again.
+fn intern(name: &str) -> &'static str {
+    static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    match names.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned = Box::leak(name.to_string().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
+/// A function defined in the DSL. Its calls are inlined by the [Context]
+/// at build time, so it is never invoked at runtime.
+struct DslFunction {
+    name: &'static str,
+    params: Vec<&'static str>,
+}
+
+impl DslFunction {
+    fn new(name: &str, params: &[String]) -> Self {
+        Self {
+            name: intern(name),
+            params: params.iter().map(|p| intern(p)).collect(),
+        }
+    }
+}
+
+impl Callable for DslFunction {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn call(&mut self, _: &[Type]) -> CallResult {
+        Err(CallError::new(
+            self.name,
+            "functions defined in the DSL are inlined at build time",
+        ))
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::exact(self.params.len())
+    }
+
+    fn of_type(&self) -> CallableType {
+        CallableType::Pure
+    }
+
+    fn signature(&self) -> Signature {
+        Signature {
+            args: vec![Kind::Any; self.params.len()],
+            rest: None,
+            params: self
+                .params
+                .iter()
+                .map(|name| Param {
+                    name,
+                    default: None,
+                })
+                .collect(),
+            returns: Kind::Any,
+        }
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Context {
+    /// Picks an overload of the function `name` that accepts given arguments.
+    /// Named arguments are bound to parameters of each overload and omitted
+    /// ones are filled with defaults before the arity check.
+    /// When several overloads match, the one with the most exactly matching
+    /// argument kinds wins. A tie is reported as an ambiguity, unless kinds
+    /// of some arguments are not known yet. Then the overload is picked by
+    /// the type checker once referenced formulas are linked.
+    /// Returns the overload along with its arguments in the positional order.
+    pub fn select_overload(
+        &self,
+        name: &str,
+        positional: &[IRNode],
+        named: &[(String, IRNode)],
+    ) -> Result<(Function, Vec<IRNode>), String> {
+        let overloads = self.find_overloads(name).ok_or_else(|| {
+            self.unresolved(
+                format!("Failed to find function with a name {}", name),
+                name,
+            )
+        })?;
+        let argcnt = positional.len() + named.len();
+        let mut bound = Vec::with_capacity(overloads.len());
+        for f in overloads {
+            match bind_arguments(f, positional, named) {
+                Ok(arguments) => bound.push((f, arguments)),
+                Err(e) if overloads.len() == 1 => return Err(e),
+                Err(_) => {}
+            }
+        }
+        let by_arity: Vec<_> = bound
+            .into_iter()
+            .filter(|(f, arguments)| f.arity().accepts(arguments.len()))
+            .collect();
+        match (overloads, by_arity.as_slice()) {
+            ([f], []) => {
+                return Err(format!(
+                    "Functions recieved unexpected number of arguments: {} ({} needed)",
+                    argcnt,
+                    f.arity()
+                ))
+            }
+            (_, []) => {
+                return Err(format!(
+                    "No overload of function {} accepts {} arguments",
+                    name, argcnt
+                ))
+            }
+            _ => {}
+        }
+
+        if by_arity.len() == 1 {
+            let (f, arguments) = by_arity.into_iter().next().unwrap();
+            check_call(f, &arguments.iter().map(kind_of).collect::<Vec<_>>())?;
+            return Ok((f.clone(), arguments));
+        }
+        let bound_kinds: Vec<Vec<_>> = by_arity
+            .iter()
+            .map(|(_, arguments)| arguments.iter().map(kind_of).collect())
+            .collect();
+        let best: Vec<_> = best_overloads(
+            by_arity
+                .iter()
+                .zip(&bound_kinds)
+                .map(|((f, _), kinds)| (*f, kinds.as_slice())),
+        )
+        .into_iter()
+        .map(|pos| &by_arity[pos])
+        .collect();
+        let deferred = best.len() > 1
+            && best[0].1.iter().any(|arg| kind_of(arg) == Kind::Any)
+            && best
+                .iter()
+                .all(|(f, arguments)| arguments == &best[0].1 && self.find_definition(f).is_none());
+        match best.as_slice() {
+            [(f, arguments)] => Ok(((*f).clone(), arguments.clone())),
+            _ if deferred => {
+                let candidates = best.iter().map(|(f, _)| (*f).clone()).collect();
+                let overloaded = builtin::Overloaded::new(name, candidates);
+                Ok((
+                    Function::from_callable(Box::new(overloaded)),
+                    best[0].1.clone(),
+                ))
+            }
+            _ => {
+                let kinds: Vec<_> = positional
+                    .iter()
+                    .chain(named.iter().map(|(_, arg)| arg))
+                    .map(kind_of)
+                    .collect();
+                Err(overload_error(name, best.len(), &kinds))
+            }
+        }
+    }
+
+    /// Returns names of functions, constants and nested namespaces
+    /// directly inside of the namespace: `ta` => `[ema, ma, pivot]`
+    pub fn namespace_members(&self, namespace: &str) -> Vec<&str> {
+        let prefix = format!("{}::", namespace);
+        let mut members: Vec<&str> = self
+            .ns_fn
+            .keys()
+            .chain(self.ns_const.keys())
+            .filter_map(|name| name.strip_prefix(prefix.as_str()))
+            .map(|member| member.split("::").next().unwrap_or(member))
+            .collect();
+        members.sort_unstable();
+        members.dedup();
+        members
+    }
+
+    /// Extends the error of unresolved qualified name with members of its namespace
+    fn unresolved(&self, message: String, name: &str) -> String {
+        let Some((namespace, _)) = name.rsplit_once("::") else {
+            return message;
+        };
+        match self.namespace_members(namespace).as_slice() {
+            [] => format!("{}. Namespace '{}' is unknown", message, namespace),
+            members => format!(
+                "{}. Namespace '{}' has: {}",
+                message,
+                namespace,
+                members.join(", ")
+            ),
+        }
+    }
+
+    /// Returns all functions registered under the `name`
+    pub fn find_overloads(&self, name: &str) -> Option<&[Function]> {
+        self.ns_fn.get(name).map(|fs| fs.as_slice())
+    }
+}
+
+/// Places named arguments at positions of matching parameters and fills
+/// omitted parameters with their default values. Trailing parameters
+/// that are still missing are left for the arity check to report.
+fn bind_arguments(
+    f: &Function,
+    positional: &[IRNode],
+    named: &[(String, IRNode)],
+) -> Result<Vec<IRNode>, String> {
+    let signature = f.signature();
+    if named.is_empty() && positional.len() >= signature.params.len() {
+        return Ok(positional.to_vec());
+    }
+    let mut slots: Vec<Option<IRNode>> = positional.iter().cloned().map(Some).collect();
+    if slots.len() < signature.params.len() {
+        slots.resize(signature.params.len(), None);
+    }
+    for (name, arg) in named {
+        let pos = signature.position_of(name).ok_or(format!(
+            "Function {} has no parameter named '{}'",
+            f.name(),
+            name
+        ))?;
+        if slots[pos].replace(arg.clone()).is_some() {
+            return Err(format!(
+                "Function {} recieved argument '{}' more than once",
+                f.name(),
+                name
+            ));
+        }
+    }
+    for (slot, param) in slots.iter_mut().zip(signature.params.iter()) {
+        if slot.is_none() {
+            *slot = param.default.clone().map(IRNode::Value);
+        }
+    }
+    while let Some(None) = slots.last() {
+        slots.pop();
+    }
+    slots
+        .into_iter()
+        .enumerate()
+        .map(|(pos, slot)| {
+            slot.ok_or(format!(
+                "Function {} is missing argument '{}'",
+                f.name(),
+                signature.params[pos].name
+            ))
+        })
+        .collect()
+}
+
 impl InterpreterContext for Context {
-    /// Registers given function in the interpreter context
+    /// Registers given function in the interpreter context.
+    /// Functions with the same name are kept as overloads unless
+    /// their argument kinds are the same. In that case the new function
+    /// replaces the old one.
     fn register_function(&mut self, name: &str, exp_fn: Box<dyn Callable>) {
-        self.ns_fn
-            .insert(name.to_string(), Function::from_callable(exp_fn));
+        let exp_fn = Function::from_callable(exp_fn);
+        let args = exp_fn.signature().args;
+        let overloads = self.ns_fn.entry(name.to_string()).or_default();
+        match overloads.iter_mut().find(|f| f.signature().args == args) {
+            Some(f) => *f = exp_fn,
+            None => overloads.push(exp_fn),
+        }
     }
 
     /// Registers given named constant in the interpreter context
-    fn register_constant(&mut self, name: &str, exp_const: f64) {
+    fn register_constant(&mut self, name: &str, exp_const: Type) {
         self.ns_const.insert(name.to_string(), exp_const);
     }
 
+    /// Returns the first registered overload of a function
     fn find_function(&self, name: &str) -> Option<&Function> {
-        self.ns_fn.get(name)
+        self.ns_fn.get(name)?.first()
     }
 
-    fn find_constant(&self, name: &str) -> Option<f64> {
-        Some(*self.ns_const.get(name)?)
+    fn find_constant(&self, name: &str) -> Option<&Type> {
+        self.ns_const.get(name)
+    }
+
+    fn find_overloads(&self, name: &str) -> &[Function] {
+        self.ns_fn.get(name).map_or(&[], Vec::as_slice)
     }
 }
 
 /// Calls functions at compiletime
 fn comptime_call_func(f: Function, arguments: Vec<IRNode>) -> Result<IRNode, String> {
-    if arguments.iter().any(|a| !matches!(a, IRNode::Value(_))) && !f.is_const() {
-        return Ok(IRNode::Function(f.clone(), arguments));
-    } else {
-        let arg_clone = arguments.clone();
-        let values: Box<[Type]> = arguments
-            .into_iter()
-            .map(|arg| match arg {
-                IRNode::Value(t) => t,
-                _ => unreachable!(),
-            })
-            .collect();
-        if let Some(result) = f.call(&*values) {
-            let result = match result {
-                Type::Function(newf) => IRNode::Function(newf, arg_clone),
-                _ => IRNode::Value(result),
-            };
-            return Ok(result);
+    let is_constant = arguments.iter().all(|arg| match arg {
+        IRNode::Value(Type::Function(g)) => g.can_be_optimized(),
+        IRNode::Value(_) => true,
+        _ => false,
+    });
+    if !is_constant && !f.is_const() {
+        return Ok(IRNode::Function(f, arguments));
+    }
+    // NOTE(iy): constructors recieve None in place of the arguments
+    // that are only known at runtime.
+    let values: Box<[Type]> = arguments
+        .iter()
+        .map(|arg| match arg {
+            IRNode::Value(t) => t.clone(),
+            _ => Type::None,
+        })
+        .collect();
+    match f.call(&values) {
+        Ok(Some(Type::Function(newf))) => Ok(IRNode::Function(newf, arguments)),
+        Ok(Some(_)) if !is_constant => {
+            Err(format!("Function {} expects constant arguments", f.name()))
         }
-        return Err(format!("Pure function with const arguments returned None"));
+        Ok(Some(result)) => Ok(IRNode::Value(result)),
+        Ok(None) => Err("Pure function with const arguments returned None".to_string()),
+        Err(e) => Err(format!(
+            "Failed to evaluate a call at build time. Reason: {}",
+            e
+        )),
     }
 }
+/// Lowers syntax into a call of a builtin function
+fn lower_builtin(f: Box<dyn Callable>, arguments: Vec<IRNode>) -> Result<IRNode, String> {
+    let f = Function::from_callable(f);
+    check_call(&f, &arguments.iter().map(kind_of).collect::<Vec<_>>())?;
+    comptime_call_func(f, arguments)
+}
+
+/// Lowers `base.field` into an accessor call. When the kind of the base is
+/// not known until runtime the field is looked up among all record kinds.
+fn lower_field(base: IRNode, name: &str) -> Result<IRNode, String> {
+    let kind = kind_of(&base);
+    let records = match kind {
+        Kind::Any => Kind::records(),
+        _ => std::slice::from_ref(&kind),
+    };
+    let mut found = records.iter().filter_map(|record| {
+        record
+            .fields()
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| (*record, f))
+    });
+    match (found.next(), found.next()) {
+        (Some((record, field)), None) => lower_builtin(
+            Box::new(builtin::FieldAccess::new(record, field)),
+            vec![base],
+        ),
+        (Some(_), Some(_)) => Err(format!(
+            "Field '{}' is ambiguous, the kind of the value is unknown",
+            name
+        )),
+        (None, _) if kind == Kind::Any => Err(format!("Unknown field '{}'", name)),
+        (None, _) => {
+            let fields = kind.fields().iter().map(|f| f.name).collect::<Vec<_>>();
+            Err(format!(
+                "{} has no field '{}'. Available fields: [{}]",
+                kind,
+                name,
+                fields.join(", ")
+            ))
+        }
+    }
+}
+
 // NOTE(iy): MAIN COMPILE TIME EVALUATION LOOP
 /// Introducing dyn InterpreterContext will degrade performance
 /// by inderection (vtable). While This visit is not important
@@ -85,18 +785,43 @@ impl Visit<Expression> for Context {
             match c {
                 Literal::Number(num) => return Ok(IRNode::Value(Type::Number(num))),
                 Literal::Ident(id) => {
+                    if let Some(local) = self.find_local(id.as_str()) {
+                        self.note_capture(&local);
+                        return Ok(local);
+                    }
                     if let Some(val) = self.find_constant(id.as_str()) {
-                        return Ok(IRNode::Value(Type::Number(val)));
+                        return Ok(match val {
+                            Type::Number(_) | Type::String(_) | Type::Array(_) => {
+                                IRNode::Value(val.clone())
+                            }
+                            _ => {
+                                let cnst = builtin::Constant::new(intern(&id), val.clone());
+                                IRNode::Function(Function::from_callable(Box::new(cnst)), vec![])
+                            }
+                        });
+                    } else if self.strict || id.contains("::") {
+                        return Err(self.unresolved(format!("Unknown constant {}", id), &id));
                     } else {
                         return Ok(IRNode::Value(Type::String(id)));
                     }
                 }
+                Literal::Str(string) => return Ok(IRNode::Value(Type::String(string))),
+                Literal::Param(name) => {
+                    return match self.ns_param.get(&name) {
+                        Some(slot) => Ok(IRNode::Param(slot.clone())),
+                        None => Err(format!("Unknown parameter ${}", name)),
+                    };
+                }
+                Literal::Duration(span, unit) => {
+                    let span = unit.convert(span, self.time_unit);
+                    return Ok(IRNode::Value(Type::Number(span)));
+                }
                 Literal::Ref(formula) => {
                     return Ok(IRNode::Ref(FormulaLink::new(formula.as_str())));
                 }
             };
         };
-        Err(format!("Tried to visit const but it has other type"))
+        Err("Tried to visit const but it has other type".to_string())
     }
 
     fn visit_fn(&self, xfn: Expression) -> Self::Returns {
@@ -106,69 +831,67 @@ impl Visit<Expression> for Context {
         } = xfn
         {
             // simplimies function arguments
-            let mut arguments = Vec::with_capacity(args.len());
+            let mut positional = Vec::with_capacity(args.len());
+            let mut named = Vec::new();
             // let object_key = (name, args.clone());
             for arg in args {
-                arguments.push(self.visit_expr(arg)?);
+                match arg {
+                    Expression::NamedArg(param, value) => {
+                        named.push((param, self.visit_expr(*value)?));
+                    }
+                    _ if !named.is_empty() => {
+                        return Err(format!(
+                            "Positional argument follows named arguments in a call to {}",
+                            name
+                        ));
+                    }
+                    _ => positional.push(self.visit_expr(arg)?),
+                }
             }
 
-            if let Some(f) = self.find_function(name.as_str()) {
-                if f.argcnt() != arguments.len() {
-                    return Err(format!(
-                        "Functions recieved unexpected number of arguments: {} ({} needed)",
-                        arguments.len(),
-                        f.argcnt()
-                    ));
-                }
-                // self.tmp_obj_lookup.insert(object_key, *f);
-                // init_object(*f, args.as_slice());
-
-                // Try to simplify fn call
-                if f.can_be_optimized() {
-                    let node = comptime_call_func(f.clone(), arguments);
-                    return node;
-                } else {
-                    return Ok(IRNode::Function(f.clone(), arguments));
-                }
+            let (f, arguments) = self.select_overload(name.as_str(), &positional, &named)?;
+            if let Some(Definition::Function { params, body, .. }) = self.find_definition(&f) {
+                let bindings = params.iter().cloned().zip(arguments).collect();
+                return self.lower_scoped(bindings, true, body.clone());
+            }
+            // self.tmp_obj_lookup.insert(object_key, *f);
+            // init_object(*f, args.as_slice());
+
+            // Try to simplify fn call
+            if f.can_be_optimized() {
+                return comptime_call_func(f, arguments);
+            } else {
+                return Ok(IRNode::Function(f, arguments));
             }
-            return Err(format!("Failed to find function with a name {}", name));
         }
-        Err(format!("Tried to visit function but it has other type"))
+        Err("Tried to visit function but it has other type".to_string())
     }
 
     fn visit_binop(&self, bin: Expression) -> Self::Returns {
         if let Expression::BinOp(lhs, rhs, op) = bin {
             let lhs = self.visit_expr(*lhs)?;
             let rhs = self.visit_expr(*rhs)?;
+            check_operands(op, &[kind_of(&lhs), kind_of(&rhs)])?;
             return match (&lhs, &rhs) {
                 (IRNode::Value(Type::Number(l)), IRNode::Value(Type::Number(r))) => {
                     Ok(IRNode::Value(Type::Number(op.eval(*l, *r))))
                 }
-                (IRNode::Value(l), IRNode::Value(r)) => {
-                    return Err(format!(
-                        "Cannot produce binary opertation between types {:?} and {:?}",
-                        l, r
-                    ))
-                }
                 _ => Ok(IRNode::BinOp(Box::new(lhs), Box::new(rhs), op)),
             };
         }
-        Err(format!(
-            "Tried to visit binary expression but it has other type"
-        ))
+        Err("Tried to visit binary expression but it has other type".to_string())
     }
 
     fn visit_unop(&self, un: Expression) -> Self::Returns {
         if let Expression::UnOp(op, e) = un {
             let rhs = self.visit_expr(*e)?;
+            check_operands(op, &[kind_of(&rhs)])?;
             if let IRNode::Value(Type::Number(rhs)) = rhs {
                 return Ok(IRNode::Value(Type::Number(op.unary_eval(rhs))));
             }
             return Ok(IRNode::UnOp(Box::new(rhs), op));
         }
-        Err(format!(
-            "Tried to visit unary expression but it has other type"
-        ))
+        Err("Tried to visit unary expression but it has other type".to_string())
     }
 
     fn visit_expr(&self, expr: Expression) -> Self::Returns {
@@ -177,15 +900,35 @@ impl Visit<Expression> for Context {
             Expression::Function { .. } => self.visit_fn(expr),
             Expression::BinOp(..) => self.visit_binop(expr),
             Expression::UnOp(..) => self.visit_unop(expr),
+            Expression::Let { name, value, body } => self.visit_let(name, *value, *body),
+            Expression::Array(items) => {
+                let items = items
+                    .into_iter()
+                    .map(|item| self.visit_expr(item))
+                    .collect::<Result<_, _>>()?;
+                lower_builtin(Box::new(builtin::__array), items)
+            }
+            Expression::Index(arr, idx) => {
+                let args = vec![self.visit_expr(*arr)?, self.visit_expr(*idx)?];
+                lower_builtin(Box::new(builtin::__index), args)
+            }
+            Expression::Field(base, name) => lower_field(self.visit_expr(*base)?, &name),
+            Expression::Lambda { params, body } => self.visit_lambda(params, *body),
+            Expression::NamedArg(name, _) => Err(format!(
+                "Named argument '{}' is allowed only in a function call",
+                name
+            )),
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod test {
     use super::*;
     use express::lang::ast::Operation;
-    use express::lang::parser::parse_expression;
+    use express::lang::operators::Assoc;
+    use express::lang::parser::{parse_expression, parse_formula_with};
     use express::prelude::*;
 
     #[runtime_callable]
@@ -198,6 +941,11 @@ mod test {
         Some(val + 1.0)
     }
 
+    #[runtime_callable(pure, export)]
+    fn twice(val: f64) -> f64 {
+        val * 2.0
+    }
+
     #[runtime_callable(pure)]
     fn add(lhs: f64, rhs: f64) -> Option<f64> {
         Some(rhs + lhs)
@@ -213,12 +961,32 @@ mod test {
         Some(astr.repeat(times))
     }
 
+    #[runtime_callable(pure)]
+    fn pick_num(_x: f64) -> f64 {
+        1.0
+    }
+
+    #[runtime_callable(pure)]
+    fn pick_two(_x: f64, _y: &Type) -> f64 {
+        2.0
+    }
+
+    #[runtime_callable(pure)]
+    fn pick_str(_x: String) -> f64 {
+        3.0
+    }
+
+    #[runtime_callable(pure)]
+    fn pick_any(_x: &Type, _y: f64) -> f64 {
+        4.0
+    }
+
     macro_rules! test_expr {
         ($expr: expr; $($cnst: expr => $cval: expr),*; $($fns: expr => $fval: expr),*) => {
             {
                 let (_, expression) = parse_expression($expr).unwrap();
                 let mut ctx = Context::new();
-                $( ctx.register_constant($cnst, $cval); )*
+                $( ctx.register_constant($cnst, $cval.into()); )*
                 $( ctx.register_function($fns, $fval); )*
                 ctx.visit_expr(expression).unwrap()
             }
@@ -260,7 +1028,7 @@ mod test {
         let result = test_expr!("-Foo * 2 + (10**2)"; "Foo" => 1.0;);
         assert_eq!(
             result,
-            IRNode::Value(Type::Number(-1.0 * 2.0 + (10.0f64.powf(2.0))))
+            IRNode::Value(Type::Number(-2.0 + (10.0f64.powf(2.0))))
         );
     }
 
@@ -338,6 +1106,634 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn test_durations() {
+        let mut ctx = Context::new();
+        let visit = |ctx: &Context, expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(
+            visit(&ctx, "5m + 500ms"),
+            Ok(IRNode::Value(Type::Number(300500.0)))
+        );
+        ctx.time_unit = TimeUnit::Seconds;
+        assert_eq!(
+            visit(&ctx, "1h / 30s"),
+            Ok(IRNode::Value(Type::Number(120.0)))
+        );
+        assert_eq!(visit(&ctx, "250ms"), Ok(IRNode::Value(Type::Number(0.25))));
+        // 0.3 / 0.001 would be 299.99999999999994
+        ctx.time_unit = TimeUnit::Milliseconds;
+        assert_eq!(visit(&ctx, "0.3s"), Ok(IRNode::Value(Type::Number(300.0))));
+        ctx.time_unit = TimeUnit::Nanoseconds;
+        assert_eq!(
+            visit(&ctx, "1.5us + 2ns"),
+            Ok(IRNode::Value(Type::Number(1502.0)))
+        );
+    }
+
+    #[test]
+    pub fn test_string_literals() {
+        let mut ctx = Context::new();
+        ctx.register_constant("PI", 3.14.into());
+        ctx.register_function("take_str", Box::new(__take_str));
+        ctx.strict = true;
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(
+            visit(r#"take_str("a \"quoted\" PI")"#),
+            Ok(IRNode::Value(Type::String(
+                "New a \"quoted\" PI ".to_string()
+            )))
+        );
+        assert_eq!(visit("PI"), Ok(IRNode::Value(Type::Number(3.14))));
+        assert_eq!(
+            visit("take_str(PIE)"),
+            Err("Unknown constant PIE".to_string())
+        );
+        assert_eq!(
+            visit("let x = 1 in x"),
+            Ok(IRNode::Value(Type::Number(1.0)))
+        );
+    }
+
+    #[test]
+    pub fn test_arrays() {
+        let mut ctx = Context::new();
+        ctx.register_function("add_answer", Box::new(__add_answer));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(
+            visit("[1, 2 * 2, 3]"),
+            Ok(IRNode::Value(Type::Array([1.0, 4.0, 3.0].into())))
+        );
+        assert_eq!(
+            visit("[1, 2, 3][1] + 1"),
+            Ok(IRNode::Value(Type::Number(3.0)))
+        );
+        assert_eq!(
+            visit("[1, 2][2]"),
+            Err("Failed to evaluate a call at build time. Reason: fn index(...) failed: index 2 is out of bounds for array of length 2".to_string())
+        );
+        assert_eq!(
+            visit("[1, abc]"),
+            Err("Function array expects argument #2 to be Number, but got String".to_string())
+        );
+        assert_eq!(
+            visit("5[0]"),
+            Err("Function index expects argument #1 to be Array, but got Number".to_string())
+        );
+        // non-constant items are collected at runtime
+        assert!(matches!(
+            visit("[add_answer(1), 2][0]"),
+            Ok(IRNode::Function(f, _)) if f.name() == "index"
+        ));
+    }
+
+    #[test]
+    pub fn test_fields() {
+        let ctx = Context::new();
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert!(matches!(
+            visit("&last.price"),
+            Ok(IRNode::Function(f, _)) if f.name() == "price"
+                && f.signature().args == vec![Kind::TimeStep]
+        ));
+        assert_eq!(
+            visit("&last.volume"),
+            Err("Unknown field 'volume'".to_string())
+        );
+        assert_eq!(
+            visit("[1, 2].price"),
+            Err("Array has no field 'price'. Available fields: []".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_lambdas() {
+        let mut ctx = Context::new();
+        ctx.register_function("map", Box::new(express_std::func::__map));
+        ctx.register_function("filter", Box::new(express_std::func::__filter));
+        ctx.register_function("dot", Box::new(express_std::math::common::__dot));
+        ctx.register_function("add_answer", Box::new(__add_answer));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert!(matches!(
+            visit("|a, b| a * b"),
+            Ok(IRNode::Value(Type::Function(f))) if f.arity() == Arity::exact(2)
+        ));
+        assert_eq!(
+            visit("let k = 2 in map(|x| x * k + 1, [1, 2])"),
+            Ok(IRNode::Value(Type::Array([3.0, 5.0].into())))
+        );
+        assert_eq!(
+            visit("filter(|x| x - 1, [1, 2, 3])"),
+            Ok(IRNode::Value(Type::Array([2.0, 3.0].into())))
+        );
+        // inner lambdas capture parameters of the outer ones
+        assert_eq!(
+            visit("map(|x| dot(filter(|y| y - x, [1, 2, 3]), [1, 1]), [1, 2])"),
+            Ok(IRNode::Value(Type::Array([5.0, 4.0].into())))
+        );
+        // the same pure function may be called while it is still running
+        assert_eq!(
+            visit("map(|x| dot(map(|y| x * y, [1, 2]), [1, 1]), [1, 2])"),
+            Ok(IRNode::Value(Type::Array([3.0, 6.0].into())))
+        );
+        // lambdas calling stateful functions are not folded
+        assert!(matches!(
+            visit("map(|x| add_answer(x), [1])"),
+            Ok(IRNode::Function(f, _)) if f.name() == "map"
+        ));
+        assert_eq!(
+            visit("|a, a| a"),
+            Err("Lambda has duplicate parameter 'a'".to_string())
+        );
+        assert_eq!(
+            visit("map(|x| x * &scale, [1])"),
+            Err(
+                "Lambda cannot refer to formula 'scale'. Pass its value as an argument instead"
+                    .to_string()
+            )
+        );
+        assert!(visit("map(|a, b| a, [1])")
+            .unwrap_err()
+            .ends_with("expects 2 arguments, but recieves 1"));
+    }
+
+    #[test]
+    pub fn test_type_mismatch() {
+        let (_, expression) = parse_expression("succ(blah)").unwrap();
+        let mut ctx = Context::new();
+        ctx.register_function("succ", Box::new(__succ));
+        assert_eq!(
+            ctx.visit_expr(expression).unwrap_err(),
+            "Function succ expects argument #1 to be Number, but got String"
+        );
+
+        let (_, expression) = parse_expression("2 + take_str(&foo)").unwrap();
+        ctx.register_function("take_str", Box::new(__take_str));
+        assert_eq!(
+            ctx.visit_expr(expression).unwrap_err(),
+            "Operator '+' expects Number operands, but got String"
+        );
+    }
+
+    #[test]
+    pub fn test_overloads() {
+        let mut ctx = Context::new();
+        ctx.register_function("pick", Box::new(__pick_num));
+        ctx.register_function("pick", Box::new(__pick_two));
+        ctx.register_function("pick", Box::new(__pick_str));
+        // same signature replaces the previous overload
+        ctx.register_function("pick", Box::new(__pick_num));
+        assert_eq!(ctx.find_overloads("pick").unwrap().len(), 3);
+
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(visit("pick(1)"), Ok(IRNode::Value(Type::Number(1.0))));
+        assert_eq!(visit("pick(1, 1)"), Ok(IRNode::Value(Type::Number(2.0))));
+        assert_eq!(visit("pick(abc)"), Ok(IRNode::Value(Type::Number(3.0))));
+        assert_eq!(
+            visit("pick(1, 2, 3)"),
+            Err("No overload of function pick accepts 3 arguments".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_ambiguous_overloads() {
+        let mut ctx = Context::new();
+        ctx.register_function("pick", Box::new(__pick_two));
+        ctx.register_function("pick", Box::new(__pick_any));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(visit("pick(1, abc)"), Ok(IRNode::Value(Type::Number(2.0))));
+        assert_eq!(visit("pick(abc, 1)"), Ok(IRNode::Value(Type::Number(4.0))));
+        assert_eq!(
+            visit("pick(1, 1)"),
+            Err(
+                "Ambiguous call to function pick. 2 overloads accept arguments of kinds (Number, Number)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[runtime_callable(pure)]
+    fn scale(x: f64, #[default = 2.0] by: f64, #[default = 0.0] shift: f64) -> f64 {
+        x * by + shift
+    }
+
+    #[test]
+    pub fn test_named_args() {
+        let mut ctx = Context::new();
+        ctx.register_function("scale", Box::new(__scale));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(visit("scale(3)"), Ok(IRNode::Value(Type::Number(6.0))));
+        assert_eq!(visit("scale(3, 3)"), Ok(IRNode::Value(Type::Number(9.0))));
+        assert_eq!(
+            visit("scale(3, shift=1)"),
+            Ok(IRNode::Value(Type::Number(7.0)))
+        );
+        assert_eq!(
+            visit("scale(shift = 1, x = 3, by = 1)"),
+            Ok(IRNode::Value(Type::Number(4.0)))
+        );
+        assert_eq!(
+            visit("scale(by=3)"),
+            Err("Function scale is missing argument 'x'".to_string())
+        );
+        assert_eq!(
+            visit("scale(3, x=3)"),
+            Err("Function scale recieved argument 'x' more than once".to_string())
+        );
+        assert_eq!(
+            visit("scale(3, offset=3)"),
+            Err("Function scale has no parameter named 'offset'".to_string())
+        );
+        assert_eq!(
+            visit("scale(by=3, 3)"),
+            Err("Positional argument follows named arguments in a call to scale".to_string())
+        );
+        assert_eq!(
+            visit("scale(3, by=abc)"),
+            Err("Function scale expects argument #2 to be Number, but got String".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_let_bindings() {
+        let mut ctx = Context::new();
+        ctx.register_constant("TWO", 2.0.into());
+        ctx.register_function("add_answer", Box::new(__add_answer));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+
+        assert_eq!(
+            visit("let x = 2 * 3 in x + 1"),
+            Ok(IRNode::Value(Type::Number(7.0)))
+        );
+        assert_eq!(
+            visit("let TWO = 3 in TWO"),
+            Ok(IRNode::Value(Type::Number(3.0)))
+        );
+        assert_eq!(
+            visit("let x = &foo in x"),
+            Ok(IRNode::Ref(FormulaLink::new("foo")))
+        );
+        assert!(ctx.take_locals().is_empty());
+
+        let shared = IRNode::Ref(FormulaLink::new("__let_x_0"));
+        assert_eq!(
+            visit("let x = add_answer(1) in x * x"),
+            Ok(IRNode::BinOp(
+                Box::new(shared.clone()),
+                Box::new(shared),
+                Operation::Times
+            ))
+        );
+        assert_eq!(
+            ctx.take_locals(),
+            vec![(
+                "__let_x_0".to_string(),
+                IRNode::Function(
+                    Function::from_callable(Box::new(__add_answer)),
+                    vec![IRNode::Value(Type::Number(1.0))]
+                )
+            )]
+        );
+
+        // unused bindings are dropped
+        assert_eq!(
+            visit("let x = add_answer(1) in let y = x in 5"),
+            Ok(IRNode::Value(Type::Number(5.0)))
+        );
+        assert!(ctx.take_locals().is_empty());
+
+        // names are visible only inside of the body
+        assert_eq!(
+            visit("(let y = 1 in y) + y"),
+            Err("Operator '+' expects Number operands, but got String".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_definitions() {
+        let mut ctx = Context::new();
+        ctx.register_function("add_answer", Box::new(__add_answer));
+        ctx.define(
+            "def zscore(x, m, s) = (x - m) / s
+             def half(x) = zscore(x, 0, 2)
+             def answer(x) = let y = add_answer(x) in y * y",
+        )
+        .unwrap();
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+
+        assert_eq!(
+            visit("zscore(10, 4, 2)"),
+            Ok(IRNode::Value(Type::Number(3.0)))
+        );
+        assert_eq!(visit("half(5)"), Ok(IRNode::Value(Type::Number(2.5))));
+        assert_eq!(
+            visit("zscore(1, s=2, m=0)"),
+            Ok(IRNode::Value(Type::Number(0.5)))
+        );
+        assert_eq!(
+            visit("half(&foo)"),
+            Ok(IRNode::BinOp(
+                Box::new(IRNode::BinOp(
+                    Box::new(IRNode::Ref(FormulaLink::new("foo"))),
+                    Box::new(IRNode::Value(Type::Number(0.0))),
+                    Operation::Minus
+                )),
+                Box::new(IRNode::Value(Type::Number(2.0))),
+                Operation::Divide
+            ))
+        );
+        // the body doesn't see names of the call site
+        assert_eq!(
+            visit("let s = 1 in zscore(1, 0, s) + answer(s)"),
+            Ok(IRNode::BinOp(
+                Box::new(IRNode::Value(Type::Number(1.0))),
+                Box::new(IRNode::BinOp(
+                    Box::new(IRNode::Ref(FormulaLink::new("__let_y_0"))),
+                    Box::new(IRNode::Ref(FormulaLink::new("__let_y_0"))),
+                    Operation::Times
+                )),
+                Operation::Plus
+            ))
+        );
+        assert_eq!(ctx.take_locals().len(), 1);
+        assert_eq!(
+            visit("zscore(1, 2)"),
+            Err("Functions recieved unexpected number of arguments: 2 (3 needed)".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_definition_overloads() {
+        let mut ctx = Context::new();
+        ctx.register_function("pick", Box::new(__pick_num));
+        ctx.register_function("pick", Box::new(__pick_two));
+        ctx.define("def pick(x) = x * 10; def pick(x, y, z) = x + y + z")
+            .unwrap();
+        // only the overload taking the same number of arguments is shadowed
+        assert_eq!(ctx.find_overloads("pick").unwrap().len(), 3);
+        let visit = |ctx: &Context, expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(
+            visit(&ctx, "pick(2)"),
+            Ok(IRNode::Value(Type::Number(20.0)))
+        );
+        assert_eq!(
+            visit(&ctx, "pick(2, 1)"),
+            Ok(IRNode::Value(Type::Number(2.0)))
+        );
+        assert_eq!(
+            visit(&ctx, "pick(1, 2, 3)"),
+            Ok(IRNode::Value(Type::Number(6.0)))
+        );
+        ctx.define("def pick(x) = x * 100").unwrap();
+        assert_eq!(ctx.ns_def["pick"].len(), 2);
+        assert_eq!(
+            visit(&ctx, "pick(2)"),
+            Ok(IRNode::Value(Type::Number(200.0)))
+        );
+        // names are allocated once
+        assert!(std::ptr::eq(intern("pick"), intern(&format!("pi{}", "ck"))));
+
+        // a variadic overload accepting as many arguments is shadowed too
+        ctx.register_function("max", Box::new(express_std::math::common::__max));
+        ctx.define("def max(a, b) = a + b").unwrap();
+        assert_eq!(ctx.find_overloads("max").unwrap().len(), 1);
+        assert_eq!(
+            visit(&ctx, "max(1, 2)"),
+            Ok(IRNode::Value(Type::Number(3.0)))
+        );
+        assert!(visit(&ctx, "max(1, 2, 3)").is_err());
+    }
+
+    #[test]
+    pub fn test_operators() {
+        let mut ctx = Context::new();
+        ctx.register_function("succ", Box::new(__succ));
+        ctx.define("def avg(a, b) = (a + b) / 2").unwrap();
+        ctx.register_operator("<>", Fixity::Infix(Assoc::Left), 10, "avg")
+            .unwrap();
+        ctx.register_operator("++", Fixity::Postfix, 50, "succ")
+            .unwrap();
+        // definitions see the registered operators
+        ctx.define("def mid(a, b) = a <> b").unwrap();
+        let visit = |expr: &str| ctx.visit_expr(parse_formula_with(expr, &ctx.operators).unwrap());
+
+        assert_eq!(visit("1 <> 3 * 2"), Ok(IRNode::Value(Type::Number(3.5))));
+        assert_eq!(visit("mid(1, 2)++"), Ok(IRNode::Value(Type::Number(2.5))));
+        assert_eq!(visit("2 ** 3 ** 2"), Ok(IRNode::Value(Type::Number(512.0))));
+        assert_eq!(visit("-(1 + 2) * 2"), Ok(IRNode::Value(Type::Number(-6.0))));
+        assert_eq!(
+            ctx.register_operator("<>", Fixity::Infix(Assoc::Right), 40, "succ"),
+            Err("Operator '<>' is already defined".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_print_ir() {
+        let mut ctx = Context::new();
+        ctx.register_function("add_answer", Box::new(__add_answer));
+        ctx.register_function("map", Box::new(express_std::func::__map));
+        let last = express::types::TimeStep {
+            price: 2.0,
+            time: 1.0,
+        };
+        ctx.register_constant("LAST", Type::TimeStep(last));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1).unwrap();
+        for expr in [
+            "add_answer(&a) * (1 + &b)",
+            "-(&a - &b) ** 2 - -1",
+            "(-&a) ** (2 ** 0.5)",
+            "add_answer(2 * 3) / &c",
+            "[&a, 2 * &b][&i] + [1, 2][1]",
+            "&last.price - LAST.time",
+            "map(|x| x * 2, &arr)",
+            "map(|x| map(|y| x * y, [1, 2])[0], &arr)",
+        ] {
+            let ir = visit(expr);
+            // printed IR lowers to the same IR
+            assert_eq!(visit(&ir.to_string()), ir, "{}", ir);
+        }
+        assert_eq!(
+            visit("add_answer(2 * 3) / (&c - 1)").to_string(),
+            "add_answer(6) / (&c - 1)"
+        );
+        assert_eq!(
+            visit("[&a, 1][0] + &last.price + LAST.time").to_string(),
+            "[&a, 1][0] + &last.price + LAST.time"
+        );
+        assert_eq!(
+            visit("map(|x| map(|y| x * y, [1]), &arr)").to_string(),
+            "map(|x| map(|y| x * y, [1]), &arr)"
+        );
+    }
+
+    #[test]
+    pub fn test_namespaces() {
+        let mut ctx = Context::new();
+        ctx.register_function("ta::succ", Box::new(__succ));
+        ctx.register_function("ta::trend::add", Box::new(__add));
+        ctx.register_function("add", Box::new(__add_answer));
+        ctx.register_constant("math::PI", 3.5.into());
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+
+        assert_eq!(
+            visit("ta::succ(math::PI)"),
+            Ok(IRNode::Value(Type::Number(4.5)))
+        );
+        assert_eq!(
+            visit("ta::trend::add(1, 2)"),
+            Ok(IRNode::Value(Type::Number(3.0)))
+        );
+        // qualified and unqualified names do not clash
+        assert!(matches!(visit("add(1)"), Ok(IRNode::Function(..))));
+        assert_eq!(ctx.namespace_members("ta"), vec!["succ", "trend"]);
+        assert_eq!(
+            visit("ta::ema(1)"),
+            Err(
+                "Failed to find function with a name ta::ema. Namespace 'ta' has: succ, trend"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            visit("math::TAU"),
+            Err("Unknown constant math::TAU. Namespace 'math' has: PI".to_string())
+        );
+        assert_eq!(
+            visit("stats::mean(1)"),
+            Err(
+                "Failed to find function with a name stats::mean. Namespace 'stats' is unknown"
+                    .to_string()
+            )
+        );
+        // only bare identifiers may be strings
+        assert_eq!(
+            visit("PI"),
+            Ok(IRNode::Value(Type::String("PI".to_string())))
+        );
+    }
+
+    #[test]
+    pub fn test_register_module() {
+        let mut ctx = Context::new();
+        ctx.register_module(module_path!()).unwrap();
+        ctx.register_module_as(module_path!(), "").unwrap();
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        // only exported callables are registered
+        assert!(ctx.find_function("ctx::test::add").is_none());
+        assert_eq!(
+            visit("ctx::test::twice(2)"),
+            Ok(IRNode::Value(Type::Number(4.0)))
+        );
+        assert_eq!(visit("twice(3)"), Ok(IRNode::Value(Type::Number(6.0))));
+
+        let mut ctx = Context::new();
+        ctx.register_function("twice", Box::new(__succ));
+        assert_eq!(
+            ctx.import_module(module_path!(), ""),
+            Err("Function 'twice' from module 'express_eval::ctx::test' is already registered with the same signature".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_register_closures() {
+        let mut ctx = Context::new();
+        ctx.register_pure_fn("hypot", |x: f64, y: f64| x.hypot(y));
+        ctx.register_stateful_fn("scale", |x: f64| x * 10.0);
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(visit("hypot(3, 4)"), Ok(IRNode::Value(Type::Number(5.0))));
+        assert!(matches!(visit("scale(1)"), Ok(IRNode::Function(..))));
+        assert_eq!(
+            visit("hypot(3, \"4\")"),
+            Err("Function hypot expects argument #2 to be Number, but got String".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_constants() {
+        let mut ctx = Context::new();
+        ctx.register_constant("PI", 3.5.into());
+        ctx.register_constant("SYMBOL", "BTCUSD".into());
+        ctx.register_constant("W", [0.25, 0.75].into());
+        let history = Type::Collection(
+            [express::types::TimeStep {
+                price: 2.0,
+                time: 1.0,
+            }]
+            .into(),
+        );
+        ctx.register_constant("HISTORY", history.clone());
+        ctx.define("const K = 2 * PI; const LAST = W[1]; def f(x) = x * K")
+            .unwrap();
+        assert_eq!(ctx.find_constant("K"), Some(&Type::Number(7.0)));
+        ctx.define("const H = HISTORY").unwrap();
+        assert_eq!(ctx.find_constant("H"), Some(&history));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(visit("f(2)"), Ok(IRNode::Value(Type::Number(14.0))));
+        assert_eq!(visit("LAST"), Ok(IRNode::Value(Type::Number(0.75))));
+        assert_eq!(
+            visit("SYMBOL"),
+            Ok(IRNode::Value(Type::String("BTCUSD".to_string())))
+        );
+        assert_eq!(
+            ctx.define("const R = &px * 2"),
+            Err("Constant R must be known at build time".to_string())
+        );
+        assert_eq!(
+            ctx.define("const PI = 3"),
+            Err("Constant PI is already defined".to_string())
+        );
+        assert_eq!(
+            ctx.define("const K = 3"),
+            Err("Constant K is already defined".to_string())
+        );
+        assert!(ctx.find_constant("R").is_none());
+    }
+
+    #[test]
+    pub fn test_recursive_definitions() {
+        let mut ctx = Context::new();
+        assert_eq!(
+            ctx.define("def f(x) = f(x - 1)"),
+            Err("Function f is recursive. Recursion is not supported".to_string())
+        );
+        ctx.define("def a(x) = b(x) + 1").unwrap();
+        assert_eq!(
+            ctx.define("def b(x) = max(a(x), 1)"),
+            Err("Function b is recursive. Recursion is not supported".to_string())
+        );
+        assert_eq!(
+            ctx.define("def g(x, x) = x"),
+            Err("Function g has duplicate parameter 'x'".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_variadic() {
+        use express_std::math::common::{__max, __min};
+        let mut ctx = Context::new();
+        ctx.register_function("max", Box::new(__max));
+        ctx.register_function("min", Box::new(__min));
+        let visit = |expr: &str| ctx.visit_expr(parse_expression(expr).unwrap().1);
+        assert_eq!(visit("max(1)"), Ok(IRNode::Value(Type::Number(1.0))));
+        assert_eq!(
+            visit("max(1, 5, 3, 2)"),
+            Ok(IRNode::Value(Type::Number(5.0)))
+        );
+        assert_eq!(
+            visit("min(4, 5, -3, 2)"),
+            Ok(IRNode::Value(Type::Number(-3.0)))
+        );
+        assert_eq!(
+            visit("max()"),
+            Err(
+                "Functions recieved unexpected number of arguments: 0 (at least 1 needed)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            visit("max(1, abc, 2)"),
+            Err("Function max expects argument #2 to be Number, but got String".to_string())
+        );
+    }
+
     #[test]
     pub fn test_reference() {
         let result = test_expr!(
@@ -1,6 +1,6 @@
 use crate::{ctx::Context, ir::IRNode};
-use express::lang::{ast::Visit, parser::parse_expression};
-use express::types::Type;
+use express::lang::{ast::Visit, parser::parse_formula_with};
+use express::types::{CallResult, Type};
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
@@ -13,6 +13,18 @@ use std::rc::{Rc, Weak};
 pub type SharedFormula = Rc<RefCell<Formula>>;
 pub type RefFormula = Weak<RefCell<Formula>>;
 
+/// Unwraps `Ok(Some(value))` of an evaluation step.
+/// Returns early with `Ok(None)` if there is no value and propagates errors.
+macro_rules! try_value {
+    ($eval:expr) => {
+        match $eval? {
+            Some(value) => value,
+            None => return Ok(None),
+        }
+    };
+}
+pub(crate) use try_value;
+
 #[derive(PartialEq, Clone)]
 pub struct Formula {
     pub name: String, // GATs!?! WHERE ARE MY GATS!?
@@ -20,6 +32,9 @@ pub struct Formula {
     pub children: Vec<SharedFormula>, // this should probably be a RefFormula
     pub parents: Vec<SharedFormula>,
     pub result: Option<Type>,
+    /// Expression of a formula folded with current parameter values.
+    /// It is folded again once one of them is changed.
+    pub unfolded: Option<IRNode>,
 }
 
 impl Debug for Formula {
@@ -49,27 +64,33 @@ impl Debug for Formula {
 }
 
 impl Iterator for Formula {
-    type Item = Type;
+    type Item = Result<Type, String>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.eval_inplace();
-        self.result.clone()
+        match self.eval_inplace() {
+            Ok(()) => self.result.clone().map(Ok),
+            Err(e) => Some(Err(e)),
+        }
     }
 }
 
 impl Formula {
     pub fn new(name: &str, expression: &str, eval_ctx: &Context) -> Result<Self, String> {
-        let (_, ast) = match parse_expression(expression) {
-            Ok(it) => it,
-            Err(err) => return Err(format!("Failed to parse expression. Reason: {}", err)),
-        };
-        Ok(Self {
+        let ast = parse_formula_with(expression, &eval_ctx.operators)
+            .map_err(|err| format!("Failed to parse expression. Reason: {}", err))?;
+        Ok(Self::from_ir(name, eval_ctx.visit_expr(ast)?))
+    }
+
+    /// Creates a formula from already lowered expression
+    pub fn from_ir(name: &str, ast: IRNode) -> Self {
+        Self {
             name: name.to_string(),
             children: vec![],
-            ast: eval_ctx.visit_expr(ast)?,
+            ast,
             parents: vec![],
             result: None,
-        })
+            unfolded: None,
+        }
     }
 
     /// Consumes formula and creates SharedFormula
@@ -77,14 +98,28 @@ impl Formula {
         Rc::new(RefCell::new(self))
     }
 
-    /// Evaluates formula and returns its result as __Option<Type>__
-    pub fn eval(&self) -> Option<Type> {
+    /// Evaluates formula and returns its result as __CallResult__
+    pub fn eval(&self) -> CallResult {
         self.visit_expr(&self.ast)
     }
 
-    /// Stores evaluation result inside .result field
-    pub fn eval_inplace(&mut self) {
-        self.result = self.visit_expr(&self.ast);
+    /// Stores evaluation result inside .result field.
+    /// On failure the result is reset and the error is reported
+    /// along with the formula name.
+    pub fn eval_inplace(&mut self) -> Result<(), String> {
+        match self.visit_expr(&self.ast) {
+            Ok(result) => {
+                self.result = result;
+                Ok(())
+            }
+            Err(e) => {
+                self.result = None;
+                Err(format!(
+                    "Failed to evaluate formula '{}'. Reason: {}",
+                    self.name, e
+                ))
+            }
+        }
     }
 
     /// inlines reference
@@ -93,18 +128,18 @@ impl Formula {
         let target_ref = rf.borrow().ast.clone();
         self.ast = self.__inline_ref(self.ast.clone(), &name, target_ref);
         // remove reference from parent
-        self.parents.retain(|el| *el != rf);
+        self.parents.retain(|el| !Rc::ptr_eq(el, &rf));
     }
 
     fn __inline_ref(&mut self, mut expr: IRNode, t_name: &str, trgt: IRNode) -> IRNode {
         match expr {
-            IRNode::Value(_) => expr,
+            IRNode::Value(_) | IRNode::Param(_) => expr,
             IRNode::Ref(ref rf) => {
                 if let IRNode::Function(..) = trgt {
                     if rf.name == t_name {
-                        return trgt.clone();
+                        trgt.clone()
                     } else {
-                        return expr;
+                        expr
                     }
                 } else {
                     unreachable!()
@@ -134,7 +169,7 @@ impl Formula {
         node_map: &BTreeMap<String, SharedFormula>,
     ) -> Result<IRNode, String> {
         match expr {
-            IRNode::Value(_) => Ok(expr),
+            IRNode::Value(_) | IRNode::Param(_) => Ok(expr),
             IRNode::Function(_, ref mut args) => {
                 for arg in args.iter_mut() {
                     *arg = self.resolve_ref(arg.clone(), node_map)?;
@@ -154,11 +189,14 @@ impl Formula {
                 if let Some(f) = node_map.get(fref.name.as_str()) {
                     // OPTIMIZATION: inline const ast
                     if let IRNode::Value(val) = &f.borrow().ast {
-                        return Ok(IRNode::Value(val.clone()));
+                        Ok(IRNode::Value(val.clone()))
                     } else {
                         fref.link_with(f);
-                        self.parents.push(f.clone());
-                        return Ok(expr);
+                        // the same formula may be referenced several times
+                        if !self.parents.iter().any(|p| Rc::ptr_eq(p, f)) {
+                            self.parents.push(f.clone());
+                        }
+                        Ok(expr)
                     }
                 } else {
                     Err(format!("Failed to find referant formula '{}'", fref.name))
@@ -169,7 +207,7 @@ impl Formula {
 }
 
 impl Visit<&IRNode> for Formula {
-    type Returns = Option<Type>;
+    type Returns = CallResult;
 
     // NOTE(iy): This call is unused because visit_expr
     // already handles extraction of a constant
@@ -195,25 +233,26 @@ impl Visit<&IRNode> for Formula {
             // Its ok for Number/TimeStep/Collection(it only clones ptr) but might be bad for
             // String.
             // FIXME: Possibly introduce currying at optimization level?
-            IRNode::Value(n) => Some((*n).clone()),
+            IRNode::Value(n) => Ok(Some((*n).clone())),
+            IRNode::Param(p) => Ok(Some(p.get())),
             IRNode::Function(fn_obj, args) => {
                 let mut resolved_args = Vec::with_capacity(args.len());
                 // resolves arguments
                 for arg in args {
-                    resolved_args.push(self.visit_expr(arg)?);
+                    resolved_args.push(try_value!(self.visit_expr(arg)));
                 }
-                Some(fn_obj.call(resolved_args.as_slice())?.into())
+                fn_obj.call(resolved_args.as_slice())
             }
             IRNode::BinOp(lhs, rhs, op) => {
-                let lhs: f64 = self.visit_expr(lhs)?.into();
-                let rhs: f64 = self.visit_expr(rhs)?.into();
-                Some(Type::Number(op.eval(lhs, rhs)))
+                let lhs: f64 = try_value!(self.visit_expr(lhs)).into();
+                let rhs: f64 = try_value!(self.visit_expr(rhs)).into();
+                Ok(Some(Type::Number(op.eval(lhs, rhs))))
             }
             IRNode::UnOp(rhs, op) => {
-                let rhs: f64 = self.visit_expr(rhs)?.into();
-                Some(Type::Number(op.unary_eval(rhs)))
+                let rhs: f64 = try_value!(self.visit_expr(rhs)).into();
+                Ok(Some(Type::Number(op.unary_eval(rhs))))
             }
-            IRNode::Ref(formula) => formula.link().as_deref()?.borrow().result.clone(),
+            IRNode::Ref(formula) => Ok(formula.link().and_then(|f| f.borrow().result.clone())),
         }
     }
 }
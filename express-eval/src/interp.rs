@@ -1,15 +1,48 @@
+#[cfg(feature = "checkpoint")]
+use crate::builtin::stateful_calls;
+#[cfg(feature = "checkpoint")]
+use crate::checkpoint::{hash_formulas, Checkpoint, Value};
 use crate::ctx::Context;
-use crate::formula::{Formula, SharedFormula};
+use crate::formula::{try_value, Formula, SharedFormula};
 use crate::ir::{FormulaLink, IRNode};
-use express::lang::ast::Visit;
-use express::types::{InterpreterContext, Type};
+use crate::typeck::TypeChecker;
+use express::lang::ast::{Expression, Visit};
+use express::lang::operators::OperatorTable;
+use express::lang::parser::parse_formula_with;
+use express::types::{CallResult, Function, InterpreterContext, Type};
 use express::xmacro::use_library;
 use std::cell::Ref;
 use std::collections::{BTreeMap, BTreeSet};
-use std::mem::swap;
+use std::mem::{replace, swap, take};
+use std::rc::Rc;
 
 type NamedExpression<'e> = (&'e str, &'e str);
 
+/// Parses every formula of the set. Parsing goes on after a failure,
+/// so that all syntax errors are reported at once.
+pub fn parse_formulas<'e>(
+    formulas: &[NamedExpression<'e>],
+    operators: &OperatorTable,
+) -> Result<Vec<(&'e str, Expression)>, String> {
+    let mut parsed = Vec::with_capacity(formulas.len());
+    let mut errors = Vec::new();
+    for (name, exp) in formulas {
+        match parse_formula_with(exp, operators) {
+            Ok(ast) => parsed.push((*name, ast)),
+            Err(e) => errors.push(format!("'{}': {}", name, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(format!(
+            "Failed to parse {} formula(s):\n{}",
+            errors.len(),
+            errors.join("\n")
+        ))
+    }
+}
+
 // NOTE(iy):
 // On Interpreter optimizations
 // |[x] Resolve references (&name)
@@ -28,6 +61,29 @@ pub struct Interpreter {
     pub ctx: Context,
     pub root_nodes: Vec<SharedFormula>,
     pub node_map: BTreeMap<String, SharedFormula>,
+    /// Lowered formulas the graph is linked from
+    sources: Vec<Source>,
+    /// Function objects of the calls promoted to formulas by their mangled
+    /// names. They are reused when the graph is linked again, so that
+    /// stateful functions of untouched formulas keep their state.
+    calls: BTreeMap<String, Function>,
+}
+
+/// Formula lowered into IR along with formulas of its let-bindings
+struct Source {
+    name: String,
+    /// Parsed formula, it identifies the formula set in checkpoints
+    #[cfg(feature = "checkpoint")]
+    expression: Expression,
+    locals: Vec<(String, IRNode)>,
+    ir: IRNode,
+}
+
+impl Source {
+    /// Tells if the formula or its let-bindings refer to the formula `name`
+    fn references(&self, name: &str) -> bool {
+        self.ir.references(name) || self.locals.iter().any(|(_, ir)| ir.references(name))
+    }
 }
 
 /// Assignes next node to a collection of parents
@@ -67,23 +123,16 @@ fn load_prelude(ctx: &mut Context) {
         context ctx;
         library express_std;
         constants {
-            math::PI;
-            math::EPS;
-            math::TAU;
-            math::LN2;
+            use math::PI;
+            use math::EPS;
+            use math::TAU;
+            use math::LN2;
         }
 
         functions {
-            math::log;
-            math::ln;
-            math::common::max;
-            math::common::min;
-            func::acc;
-            timeseries::ema;
-            timeseries::jma;
-            timeseries::ma;
-            timeseries::malin;
-            timeseries::twa;
+            use math::*;
+            use func::*;
+            use timeseries::*;
         }
     }
 }
@@ -94,20 +143,18 @@ pub struct IntrerpterIt {
 }
 
 impl Iterator for IntrerpterIt {
-    type Item = BTreeMap<String, Option<Type>>;
+    type Item = Result<BTreeMap<String, Option<Type>>, String>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let result = self.interpreter.compute_pass(&mut self.children_buf);
-        if result.iter().all(|(_, val)| val.is_none()) {
-            None
-        } else {
-            Some(result)
+        match self.interpreter.compute_pass(&mut self.children_buf) {
+            Ok(result) if result.iter().all(|(_, val)| val.is_none()) => None,
+            result => Some(result),
         }
     }
 }
 
 impl IntoIterator for Interpreter {
-    type Item = BTreeMap<String, Option<Type>>;
+    type Item = Result<BTreeMap<String, Option<Type>>, String>;
 
     type IntoIter = IntrerpterIt;
 
@@ -138,42 +185,180 @@ impl Interpreter {
     pub fn new(formulas: &[NamedExpression], mut context: Context) -> Result<Self, String> {
         // Load standard library
         load_prelude(&mut context);
-        // TODO: optimization -> Make DAGbld struct that builds dag and holds node_map
-        // since it would be unused after the DAG has been created
-        let mut node_map: BTreeMap<String, SharedFormula> = BTreeMap::new();
-        let mut nodes = Vec::new();
-        for (name, exp) in formulas {
-            let formula = Formula::new(name, exp, &context)?;
-            nodes.push((name.to_string(), formula.clone()));
-            node_map.insert(name.to_string(), formula.make_shared());
+        let mut sources = Vec::with_capacity(formulas.len());
+        for (name, ast) in parse_formulas(formulas, &context.operators)? {
+            let ir = context.visit_expr(ast.clone())?;
+            sources.push(Source {
+                name: name.to_string(),
+                #[cfg(feature = "checkpoint")]
+                expression: ast,
+                // values shared by let-bindings are computed by hidden formulas
+                locals: context.take_locals(),
+                ir,
+            });
         }
         let mut intrp = Self {
             ctx: context,
-            node_map,
+            node_map: BTreeMap::new(),
             root_nodes: vec![],
+            sources,
+            calls: BTreeMap::new(),
         };
 
-        intrp.build_dag(nodes.into_iter())?;
+        intrp.link(None)?;
 
         Ok(intrp)
     }
 
+    /// Adds a formula or replaces the one with the same name between ticks.
+    /// Other formulas keep the state of their functions, and those that don't
+    /// depend on it keep their results as well. If the new formula fails to
+    /// build, the interpreter and its context are left as they were.
+    /// The graph is linked again as a whole, so nodes of other formulas
+    /// are replaced by new ones.
+    pub fn upsert_formula(&mut self, name: &str, expression: &str) -> Result<(), String> {
+        let ast = parse_formula_with(expression, &self.ctx.operators)
+            .map_err(|e| format!("Failed to parse formula '{}'. Reason: {}", name, e))?;
+        let mark = self.ctx.mark();
+        let result = self.upsert_lowered(name, ast);
+        if result.is_err() {
+            self.ctx.rollback(mark);
+        }
+        result
+    }
+
+    fn upsert_lowered(&mut self, name: &str, ast: Expression) -> Result<(), String> {
+        let ir = self.ctx.visit_expr(ast.clone())?;
+        let source = Source {
+            name: name.to_string(),
+            #[cfg(feature = "checkpoint")]
+            expression: ast,
+            locals: self.ctx.take_locals(),
+            ir,
+        };
+        match self.sources.iter().position(|s| s.name == name) {
+            Some(pos) => {
+                let previous = replace(&mut self.sources[pos], source);
+                self.link(Some(name))
+                    .inspect_err(|_| self.sources[pos] = previous)
+            }
+            None => {
+                self.sources.push(source);
+                self.link(Some(name)).inspect_err(|_| {
+                    self.sources.pop();
+                })
+            }
+        }
+    }
+
+    /// Removes a formula between ticks. Formulas that refer to it
+    /// must be removed first.
+    pub fn remove_formula(&mut self, name: &str) -> Result<(), String> {
+        let pos = self
+            .sources
+            .iter()
+            .position(|s| s.name == name)
+            .ok_or_else(|| format!("Unknown formula '{}'", name))?;
+        if let Some(other) = self
+            .sources
+            .iter()
+            .find(|s| s.name != name && s.references(name))
+        {
+            return Err(format!(
+                "Formula '{}' is referenced by '{}'",
+                name, other.name
+            ));
+        }
+        let removed = self.sources.remove(pos);
+        self.link(None)
+            .inspect_err(|_| self.sources.insert(pos, removed))
+    }
+
+    /// Links lowered formulas into a new graph. Function objects of the
+    /// previous one are reused, so that their state is preserved. Formulas
+    /// not reached from the `changed` one keep their results as well.
+    /// The previous graph is restored on failure.
+    ///
+    /// The whole graph is linked again rather than the changed subgraph:
+    /// a call shared by several formulas is promoted to a formula of its own
+    /// and a call used once is inlined, so a change may reshape formulas that
+    /// don't depend on it.
+    fn link(&mut self, changed: Option<&str>) -> Result<(), String> {
+        let node_map = take(&mut self.node_map);
+        let root_nodes = take(&mut self.root_nodes);
+        let calls = self.calls.clone();
+        let mut retired = take(&mut self.calls);
+        let mut nodes = Vec::new();
+        for source in &self.sources {
+            for (local, ir) in &source.locals {
+                nodes.push(local.clone());
+                let formula = Formula::from_ir(local, ir.clone());
+                self.node_map.insert(local.clone(), formula.make_shared());
+            }
+            nodes.push(source.name.clone());
+            let formula = Formula::from_ir(&source.name, source.ir.clone());
+            self.node_map
+                .insert(source.name.clone(), formula.make_shared());
+        }
+        let result = self.build_dag(nodes.into_iter(), &mut retired);
+        if result.is_err() {
+            self.node_map = node_map;
+            self.root_nodes = root_nodes;
+            self.calls = calls;
+            return result;
+        }
+        let affected = changed.map_or_else(BTreeSet::new, |name| self.dependents_of(name));
+        for (name, formula) in &self.node_map {
+            if affected.contains(name) {
+                continue;
+            }
+            if let Some(previous) = node_map.get(name) {
+                formula.borrow_mut().result = previous.borrow().result.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns names of the formula, its let-bindings and of the formulas
+    /// that depend on them
+    fn dependents_of(&self, name: &str) -> BTreeSet<String> {
+        let source = self.sources.iter().find(|source| source.name == name);
+        let mut stack: Vec<SharedFormula> = source
+            .into_iter()
+            .flat_map(|source| source.locals.iter().map(|(local, _)| local.as_str()))
+            .chain([name])
+            .filter_map(|name| self.node_map.get(name).cloned())
+            .collect();
+        let mut found = BTreeSet::new();
+        while let Some(formula) = stack.pop() {
+            let formula = formula.borrow();
+            if found.insert(formula.name.clone()) {
+                stack.extend(formula.children.iter().cloned());
+            }
+        }
+        found
+    }
+
     /// Creates a Direct Acyclic Graph for the stage execution.
     /// Refernces introduce dependencies and therefore they should be
     /// managed in a tree-flow fashion.
-    fn build_dag<It>(&mut self, nodes: It) -> Result<(), String>
+    fn build_dag<It>(
+        &mut self,
+        nodes: It,
+        retired: &mut BTreeMap<String, Function>,
+    ) -> Result<(), String>
     where
-        It: Iterator<Item = (String, Formula)>,
+        It: Iterator<Item = String>,
     {
         // order matters.
         // Unused links will be resolved in a reverse order
         let mut unused = Vec::new();
-        for (name, _) in nodes.into_iter() {
+        for name in nodes {
             let fnode = self.node_map.get(&name).unwrap().clone();
             let mut fnode_inner = fnode.borrow_mut();
 
             // This would ensure that previous formula functions gets referenced
-            self.manage_references(&mut fnode_inner, &mut unused)?;
+            self.manage_references(&mut fnode_inner, &mut unused, retired)?;
 
             if fnode_inner.parents.is_empty() {
                 self.root_nodes.push(fnode.clone());
@@ -184,10 +369,11 @@ impl Interpreter {
 
         self.remove_redundant_references(unused.as_slice())?;
         self.assert_dag_has_no_cycles()?;
+        TypeChecker::new().check_all(&self.node_map)?;
         self.opt_const_eval();
 
         if self.root_nodes.is_empty() {
-            Err(format!("Root nodes are empty. Execution graph is invalid"))
+            Err("Root nodes are empty. Execution graph is invalid".to_string())
         } else {
             Ok(())
         }
@@ -197,10 +383,11 @@ impl Interpreter {
         &mut self,
         formula: &mut Formula,
         unused: &mut Vec<String>,
+        retired: &mut BTreeMap<String, Function>,
     ) -> Result<(), String> {
         let mut ir = formula.ast.clone();
         // optimization: Incapsulate repeating functions in a separate formula
-        ir = self._find_dup_fns(unused, ir.clone());
+        ir = self._find_dup_fns(unused, retired, ir.clone())?;
         // resolve references (links everything together)
         formula.ast = formula.resolve_ref(ir.clone(), &self.node_map)?;
         Ok(())
@@ -221,7 +408,15 @@ impl Interpreter {
                 }
 
                 let ref_origin = shared_f.borrow_mut().children.pop().unwrap();
-                ref_origin.borrow_mut().inline_ref(shared_f);
+                ref_origin.borrow_mut().inline_ref(shared_f.clone());
+                // parents of the inlined formula now feed its origin directly
+                for parent in &shared_f.borrow().parents {
+                    let mut parent = parent.borrow_mut();
+                    parent.children.retain(|ch| !Rc::ptr_eq(ch, &shared_f));
+                    if !parent.children.iter().any(|ch| Rc::ptr_eq(ch, &ref_origin)) {
+                        parent.children.push(ref_origin.clone());
+                    }
+                }
 
                 let refast = ref_origin.borrow().ast.clone();
                 let mut reforigin_mut = ref_origin.borrow_mut();
@@ -231,7 +426,7 @@ impl Interpreter {
 
         // and assign new starting nodes
         for v in self.node_map.values() {
-            if v.borrow().parents.is_empty() {
+            if v.borrow().parents.is_empty() && !self.root_nodes.iter().any(|r| Rc::ptr_eq(r, v)) {
                 self.root_nodes.push(v.clone());
             }
         }
@@ -243,24 +438,28 @@ impl Interpreter {
     /// This optimization allows the compiler to initialize stateful
     /// functions only once and later compute them seperately to reuse
     /// their result.
-    fn _find_dup_fns(&mut self, unused: &mut Vec<String>, mut expr: IRNode) -> IRNode {
+    fn _find_dup_fns(
+        &mut self,
+        unused: &mut Vec<String>,
+        retired: &mut BTreeMap<String, Function>,
+        mut expr: IRNode,
+    ) -> Result<IRNode, String> {
         let fname = Interpreter::mangle_fname(&expr);
         match expr {
-            IRNode::Value(_) => expr,
+            IRNode::Value(_) | IRNode::Param(_) => Ok(expr),
             IRNode::Ref(ref mut rf) => {
                 if rf.count() <= 1 && rf.link().is_some() {
                     self.node_map.remove(&rf.name);
                     let f = rf.link().unwrap().clone();
-                    drop(rf);
                     let f = f.borrow_mut().ast.clone();
-                    return f;
+                    return Ok(f);
                 }
-                expr
+                Ok(expr)
             }
             IRNode::Function(ref func, ref mut args) => {
                 // TODO: add the same optimization for arguments
                 for arg in args.iter_mut() {
-                    *arg = self._find_dup_fns(unused, arg.clone());
+                    *arg = self._find_dup_fns(unused, retired, arg.clone())?;
                 }
 
                 // mangle formula name and check its presents
@@ -269,15 +468,19 @@ impl Interpreter {
                     let mut link = FormulaLink::new(&fname);
                     link.link_with(val);
 
-                    return IRNode::Ref(link);
+                    Ok(IRNode::Ref(link))
                 } else {
+                    // the same call of the previous graph keeps its state
+                    let func = retired.remove(&fname).unwrap_or_else(|| func.clone());
+                    self.calls.insert(fname.clone(), func.clone());
                     // create formula
                     let f = Formula {
-                        ast: IRNode::Function(func.clone(), args.clone()),
+                        ast: IRNode::Function(func, args.clone()),
                         children: vec![],
                         parents: vec![],
                         name: fname.clone(),
                         result: None,
+                        unfolded: None,
                     };
 
                     let shared_f = f.make_shared();
@@ -291,29 +494,32 @@ impl Interpreter {
                     let mut shared = shared_f.borrow_mut();
                     let ast = shared.ast.clone();
                     // Resolve additional references inside of arguments
-                    shared.ast = shared.resolve_ref(ast, &self.node_map).unwrap();
+                    shared.ast = shared.resolve_ref(ast, &self.node_map)?;
                     link_child_with_parents(shared.parents.as_mut_slice(), shared_f.clone());
 
-                    return IRNode::Ref(link);
+                    Ok(IRNode::Ref(link))
                 }
             }
             IRNode::BinOp(ref mut lhs, ref mut rhs, _) => {
-                **lhs = self._find_dup_fns(unused, *lhs.clone());
-                **rhs = self._find_dup_fns(unused, *rhs.clone());
-                expr
+                **lhs = self._find_dup_fns(unused, retired, *lhs.clone())?;
+                **rhs = self._find_dup_fns(unused, retired, *rhs.clone())?;
+                Ok(expr)
             }
             IRNode::UnOp(ref mut lhs, _) => {
-                **lhs = self._find_dup_fns(unused, *lhs.clone());
-                expr
+                **lhs = self._find_dup_fns(unused, retired, *lhs.clone())?;
+                Ok(expr)
             }
         }
     }
 
     /// Inline const result evaluation
     fn opt_const_eval(&self) {
-        for (_, f) in &self.node_map {
+        for f in self.node_map.values() {
             let mut formula = f.borrow_mut();
             if let Some(val) = self._opt_const_eval_walk(&formula.ast) {
+                if formula.ast.has_params() {
+                    formula.unfolded = Some(formula.ast.clone());
+                }
                 formula.ast = val;
             }
         }
@@ -324,7 +530,11 @@ impl Interpreter {
             // NOTE(iy): smelly part. We have to clone values.
             // Its ok for Number/TimeStep/Collection(it only clones ptr) but might be bad for
             // String.
+            // lambdas reading parameters or calling stateful functions
+            // keep the calls they are passed to from being folded
+            IRNode::Value(Type::Function(f)) if !f.can_be_optimized() => None,
             IRNode::Value(_) => Some(expr.clone()),
+            IRNode::Param(p) => Some(IRNode::Value(p.get())),
             IRNode::Function(fn_obj, args) => {
                 if !fn_obj.can_be_optimized() {
                     return None;
@@ -340,9 +550,11 @@ impl Interpreter {
                     resolved_args.push(arg.into());
                 }
 
-                match fn_obj.call(resolved_args.as_slice())? {
+                // NOTE(iy): failed calls are left as is. The error gets
+                // reported with the formula context during evaluation.
+                match fn_obj.call(resolved_args.as_slice()).ok()?? {
                     Type::Function(f) => Some(IRNode::Function(f, collected)),
-                    r @ _ => Some(IRNode::Value(r.into())),
+                    r => Some(IRNode::Value(r)),
                 }
             }
             IRNode::BinOp(lhs, rhs, op) => {
@@ -356,9 +568,32 @@ impl Interpreter {
             }
             IRNode::Ref(formula) => {
                 let result = formula.link().as_deref()?.borrow().result.as_ref()?.clone();
-                Some(IRNode::Value(result.into()))
+                Some(IRNode::Value(result))
+            }
+        }
+    }
+
+    /// Sets a parameter like `$threshold` between ticks. Formulas are kept
+    /// along with the state of their functions, only the ones folded with
+    /// the previous value of the parameter are folded again.
+    pub fn set_param(&self, name: &str, value: Type) -> Result<(), String> {
+        let slot = self
+            .ctx
+            .ns_param
+            .get(name)
+            .ok_or_else(|| format!("Unknown parameter ${}", name))?;
+        slot.set(value)?;
+        for formula in self.node_map.values() {
+            let mut formula = formula.borrow_mut();
+            let Some(unfolded) = formula.unfolded.clone() else {
+                continue;
+            };
+            if unfolded.uses_param(name) {
+                // a failed call is left to report its error during evaluation
+                formula.ast = self._opt_const_eval_walk(&unfolded).unwrap_or(unfolded);
             }
         }
+        Ok(())
     }
 
     fn assert_dag_has_no_cycles(&self) -> Result<(), String> {
@@ -373,21 +608,22 @@ impl Interpreter {
     }
 
     /// Evaluates formula
-    pub fn eval(&self, formula: Ref<Formula>) -> Option<Type> {
+    pub fn eval(&self, formula: Ref<Formula>) -> CallResult {
         self.visit_expr(&formula.ast)
     }
 
-    /// calculates a single iteration of the eval loop
+    /// calculates a single iteration of the eval loop.
+    /// Stops at the first formula that fails to evaluate.
     pub fn compute_pass(
         &self,
         children_buf: &mut Vec<SharedFormula>,
-    ) -> BTreeMap<String, Option<Type>> {
+    ) -> Result<BTreeMap<String, Option<Type>>, String> {
         let mut active_nodes = self.root_nodes.clone();
         let mut results = BTreeMap::new();
         while !active_nodes.is_empty() {
             for node in &active_nodes {
                 let mut formula = node.borrow_mut();
-                formula.eval_inplace();
+                formula.eval_inplace()?;
                 children_buf.extend(formula.children.clone());
                 if formula.children.is_empty() && !results.contains_key(&formula.name) {
                     results.insert(formula.name.clone(), formula.result.clone());
@@ -396,7 +632,7 @@ impl Interpreter {
             swap(&mut active_nodes, children_buf);
             children_buf.clear();
         }
-        results
+        Ok(results)
     }
 
     pub fn _eval_threaded(&self, _th_num: usize) -> &[Option<Type>] {
@@ -404,12 +640,152 @@ impl Interpreter {
     }
 }
 
-/// Implements interator trait over interpreter.
-/// The return value of the `next` is a Box ptr to
-/// the slice of `Type`.
-/// Why `Box<T>`? GATs at the moment are unstable
-/// and the only way to use them is by swithing to
-/// the nightly toolchain.
+#[cfg(feature = "checkpoint")]
+impl Interpreter {
+    /// Captures last formula results and states of stateful functions,
+    /// so that an interpreter of the same formula set can resume the
+    /// computation after a restart without warming up again.
+    pub fn checkpoint(&self) -> Result<Vec<u8>, String> {
+        let results = self
+            .sources
+            .iter()
+            .filter_map(|source| {
+                let formula = self.node_map.get(&source.name)?.borrow();
+                let result = Value::from_type(formula.result.as_ref()?)?;
+                Some((source.name.clone(), result))
+            })
+            .collect();
+        let mut states = BTreeMap::new();
+        for (name, func) in self.stateful_calls() {
+            let state = func
+                .save_state()
+                .map_err(|e| format!("Failed to save state of '{}'. Reason: {}", name, e))?
+                .ok_or_else(|| {
+                    format!(
+                        "Failed to save state of '{}'. Reason: {} can't save its state",
+                        name,
+                        func.name()
+                    )
+                })?;
+            states.insert(name, state);
+        }
+        Checkpoint {
+            formulas: self.formula_set_hash(),
+            results,
+            states,
+        }
+        .encode()
+    }
+
+    /// Resumes the computation from a checkpoint. It is rejected if made for
+    /// another formula set, in which case the interpreter is left as it was.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let checkpoint = Checkpoint::decode(bytes)?;
+        if checkpoint.formulas != self.formula_set_hash() {
+            return Err("Checkpoint was made for another set of formulas".to_string());
+        }
+        let calls = self.stateful_calls();
+        if let Some(name) = checkpoint
+            .states
+            .keys()
+            .find(|name| !calls.contains_key(*name))
+        {
+            return Err(format!("Checkpoint has a state of unknown call '{}'", name));
+        }
+        let backup = Checkpoint::decode(&self.checkpoint()?)?;
+        self.load_checkpoint(&calls, checkpoint).inspect_err(|_| {
+            // the backup has been taken from these very functions
+            let _ = self.load_checkpoint(&calls, backup);
+        })
+    }
+
+    fn load_checkpoint(
+        &self,
+        calls: &BTreeMap<String, Function>,
+        mut checkpoint: Checkpoint,
+    ) -> Result<(), String> {
+        for (name, state) in &checkpoint.states {
+            calls[name]
+                .load_state(state)
+                .map_err(|e| format!("Failed to restore state of '{}'. Reason: {}", name, e))?;
+        }
+        for source in &self.sources {
+            if let Some(formula) = self.node_map.get(&source.name) {
+                formula.borrow_mut().result =
+                    checkpoint.results.remove(&source.name).map(Type::from);
+            }
+        }
+        Ok(())
+    }
+
+    /// Identifies the formula set by the parsed formulas, which unlike
+    /// the lowered ones don't depend on the counters of the context
+    fn formula_set_hash(&self) -> u64 {
+        let formulas = self
+            .sources
+            .iter()
+            .map(|source| (source.name.as_str(), source.expression.to_string()));
+        hash_formulas(formulas)
+    }
+
+    /// Finds functions of the graph that keep a state by keys that survive
+    /// a restart: the formula name and the position of the call in it, e.g.
+    /// `sum#0`. A call shared by several formulas is keyed by the first of
+    /// them in the order of names.
+    fn stateful_calls(&self) -> BTreeMap<String, Function> {
+        let mut sources: Vec<_> = self.sources.iter().collect();
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut calls = BTreeMap::new();
+        let mut seen: Vec<Function> = Vec::new();
+        for source in sources {
+            let mut found = Vec::new();
+            let names = source.locals.iter().map(|(name, _)| name);
+            for name in names.chain([&source.name]) {
+                if let Some(formula) = self.node_map.get(name) {
+                    self.collect_stateful(&formula.borrow().ast, &mut found);
+                }
+            }
+            for (pos, func) in found.into_iter().enumerate() {
+                if !seen
+                    .iter()
+                    .any(|f| Rc::ptr_eq(&f.clone_rc(), &func.clone_rc()))
+                {
+                    seen.push(func.clone());
+                    calls.insert(format!("{}#{}", source.name, pos), func);
+                }
+            }
+        }
+        calls
+    }
+
+    /// Collects stateful functions of the node like [stateful_calls],
+    /// but also of the calls promoted to formulas it refers to
+    fn collect_stateful(&self, node: &IRNode, found: &mut Vec<Function>) {
+        match node {
+            IRNode::Ref(link) if self.calls.contains_key(&link.name) => {
+                if let Some(formula) = link.link() {
+                    self.collect_stateful(&formula.borrow().ast, found);
+                }
+            }
+            IRNode::Function(f, args) if f.can_be_optimized() => args
+                .iter()
+                .for_each(|arg| self.collect_stateful(arg, found)),
+            IRNode::BinOp(lhs, rhs, _) => {
+                self.collect_stateful(lhs, found);
+                self.collect_stateful(rhs, found);
+            }
+            IRNode::UnOp(rhs, _) => self.collect_stateful(rhs, found),
+            _ => stateful_calls(node, found),
+        }
+    }
+}
+
+// Implements interator trait over interpreter.
+// The return value of the `next` is a Box ptr to
+// the slice of `Type`.
+// Why `Box<T>`? GATs at the moment are unstable
+// and the only way to use them is by swithing to
+// the nightly toolchain.
 // impl Iterator for Interpreter {
 //     /// GATs are unstable at the moment.
 //     /// We can not write &'r Option<Type>
@@ -421,7 +797,7 @@ impl Interpreter {
 // }
 
 impl Visit<&IRNode> for Interpreter {
-    type Returns = Option<Type>;
+    type Returns = CallResult;
 
     // NOTE(iy): This call is unused because visit_expr
     // already handles extraction of a constant
@@ -447,35 +823,34 @@ impl Visit<&IRNode> for Interpreter {
             // Its ok for Number/TimeStep/Collection(it only clones ptr) but might be bad for
             // String.
             // FIXME: Possibly introduce currying at optimization level?
-            IRNode::Value(n) => Some((*n).clone()),
+            IRNode::Value(n) => Ok(Some((*n).clone())),
+            IRNode::Param(p) => Ok(Some(p.get())),
             IRNode::Function(fn_obj, args) => {
                 let mut resolved_args = Vec::with_capacity(args.len());
                 // resolves arguments
                 for arg in args {
-                    resolved_args.push(self.visit_expr(arg)?);
+                    resolved_args.push(try_value!(self.visit_expr(arg)));
                 }
-                Some(fn_obj.call(resolved_args.as_slice())?.into())
+                fn_obj.call(resolved_args.as_slice())
             }
             IRNode::BinOp(lhs, rhs, op) => {
-                let lhs: f64 = self.visit_expr(lhs)?.into();
-                let rhs: f64 = self.visit_expr(rhs)?.into();
-                Some(Type::Number(op.eval(lhs, rhs)))
+                let lhs: f64 = try_value!(self.visit_expr(lhs)).into();
+                let rhs: f64 = try_value!(self.visit_expr(rhs)).into();
+                Ok(Some(Type::Number(op.eval(lhs, rhs))))
             }
             IRNode::UnOp(rhs, op) => {
-                let rhs: f64 = self.visit_expr(rhs)?.into();
-                Some(Type::Number(op.unary_eval(rhs)))
+                let rhs: f64 = try_value!(self.visit_expr(rhs)).into();
+                Ok(Some(Type::Number(op.unary_eval(rhs))))
             }
-            IRNode::Ref(formula) => formula.link().as_deref()?.borrow().result.clone(),
+            IRNode::Ref(formula) => Ok(formula.link().and_then(|f| f.borrow().result.clone())),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-
-    use std::rc::Rc;
-
     use super::*;
+    use crate::builtin::Overloaded;
     use express::prelude::*;
 
     #[runtime_callable]
@@ -483,11 +858,21 @@ mod test {
         Some(x + y)
     }
 
+    #[runtime_callable]
+    fn fail(x: f64) -> Result<f64, String> {
+        Err(format!("cannot handle {}", x))
+    }
+
+    #[runtime_callable]
+    fn tick(price: f64, time: f64) -> express::types::TimeStep {
+        express::types::TimeStep { price, time }
+    }
+
     macro_rules! test_expr {
         ($($cnst: expr => $cval: expr),*; $($fns: expr => $fval: expr),*) => {
             {
                 let mut ctx = Context::new();
-                $( ctx.register_constant($cnst, $cval); );*
+                $( ctx.register_constant($cnst, $cval.into()); );*
                 $( ctx.register_function($fns, $fval); );*
                 ctx
             }
@@ -499,7 +884,7 @@ mod test {
         let ctx = test_expr!(; "add" => Box::new(resolve_name!(add)));
         let i = Interpreter::new(&[("foo", "2 + add(12 - 2, add(1, 1))")], ctx).unwrap();
         let f = i.node_map.get("foo").unwrap();
-        let result: f64 = i.eval(f.borrow()).unwrap().into();
+        let result: f64 = i.eval(f.borrow()).unwrap().unwrap().into();
         assert_eq!(result, 14.0);
     }
 
@@ -507,7 +892,7 @@ mod test {
     pub fn expr_with_std_call() {
         let intrp = Interpreter::new(&[("foo", "2+2*2+log(2,4)")], Context::new()).unwrap();
         let f = intrp.node_map.get("foo").unwrap();
-        let result: i64 = intrp.eval(f.borrow()).unwrap().into();
+        let result: i64 = intrp.eval(f.borrow()).unwrap().unwrap().into();
         assert_eq!(result, 8);
     }
 
@@ -518,7 +903,7 @@ mod test {
         let intrp =
             Interpreter::new(&[("foo", "2+2*2+add(2,4)"), ("bar", "&foo * 2")], ctx).unwrap();
         let f = intrp.node_map.get("foo").unwrap();
-        let result: i64 = intrp.eval(f.borrow()).unwrap().into();
+        let result: i64 = intrp.eval(f.borrow()).unwrap().unwrap().into();
         assert_eq!(result, 12);
         assert!(!f.borrow().children.is_empty());
         let next_from_root = intrp.node_map.get("bar").unwrap().clone();
@@ -576,7 +961,7 @@ mod test {
             &[("foo", "11 + &bary"), ("bary", "&foo + 11")],
             Context::new(),
         );
-        assert!(matches!(intrp, Err(_)));
+        assert!(intrp.is_err());
     }
 
     #[test]
@@ -615,7 +1000,7 @@ mod test {
             ctx,
         )
         .unwrap();
-        let result = intrp.compute_pass(&mut intrp.root_nodes.clone());
+        let result = intrp.compute_pass(&mut intrp.root_nodes.clone()).unwrap();
         assert!(!result.is_empty());
         assert_eq!(result["f1"], Some(Type::Number(13.0)));
         assert_eq!(result["f2"], Some(Type::Number(6.0)));
@@ -634,7 +1019,7 @@ mod test {
             ctx,
         )
         .unwrap();
-        let result = intrp.compute_pass(&mut intrp.root_nodes.clone());
+        let result = intrp.compute_pass(&mut intrp.root_nodes.clone()).unwrap();
         assert!(!result.is_empty());
         assert_eq!(result.len(), 1);
         assert_eq!(result["f3"], Some(Type::Number(19.0)));
@@ -654,23 +1039,503 @@ mod test {
         )
         .unwrap();
         let mut iit = intrp.into_iter();
-        let result = iit.next().unwrap();
+        let result = iit.next().unwrap().unwrap();
         assert!(!result.is_empty());
         // for result in intrp  <-- results in an inf loop, since functions cant return None
     }
 
+    #[test]
+    pub fn test_compute_pass_inlined_chain() {
+        // `acc(0, &a)` is inlined into `f`, so `a` has to feed `f` directly
+        let intrp = Interpreter::new(
+            &[("a", "acc(0, 1)"), ("f", "max(acc(0, &a), 0)")],
+            Context::new(),
+        )
+        .unwrap();
+        let result = intrp.compute_pass(&mut vec![]).unwrap();
+        assert_eq!(result["f"], Some(Type::Number(1.0)));
+        let result = intrp.compute_pass(&mut vec![]).unwrap();
+        assert_eq!(result["f"], Some(Type::Number(3.0)));
+    }
+
     #[test]
     pub fn test_state_fn() {
         let mut ctx = Context::new();
         ctx.register_function("add", Box::new(__add));
         let intrp = Interpreter::new(&[("f1", "acc(0, 2*2)")], ctx).unwrap();
         let f = intrp.root_nodes[0].clone();
-        let r0: f64 = intrp.eval(f.borrow()).unwrap().into();
-        let r1: f64 = intrp.eval(f.borrow()).unwrap().into();
-        let r2: f64 = intrp.eval(f.borrow()).unwrap().into();
+        let r0: f64 = intrp.eval(f.borrow()).unwrap().unwrap().into();
+        let r1: f64 = intrp.eval(f.borrow()).unwrap().unwrap().into();
+        let r2: f64 = intrp.eval(f.borrow()).unwrap().unwrap().into();
 
         assert_eq!(r0, 4.0);
         assert_eq!(r1, 8.0);
         assert_eq!(r2, 12.0);
     }
+
+    #[test]
+    pub fn test_type_check_through_refs() {
+        let intrp = Interpreter::new(&[("foo", "blah"), ("bar", "ln(&foo)")], Context::new());
+        assert_eq!(
+            intrp.err().unwrap(),
+            "Type error in formula 'bar'. Function ln expects argument #1 to be Number, but got String"
+        );
+
+        let intrp = Interpreter::new(
+            &[("foo", "acc(0, 1)"), ("bar", "ma(&foo, 10)")],
+            Context::new(),
+        );
+        assert_eq!(
+            intrp.err().unwrap(),
+            "Type error in formula 'bar'. Function ma expects argument #1 to be Collection, but got Number"
+        );
+
+        let intrp = Interpreter::new(&[("foo", "2 * 2"), ("bar", "ma(&foo, 10)")], Context::new());
+        assert_eq!(
+            intrp.err().unwrap(),
+            "Type error in formula 'bar'. Function ma expects argument #1 to be Collection, but got Number"
+        );
+    }
+
+    #[test]
+    pub fn test_let_bindings() {
+        let intrp = Interpreter::new(
+            &[
+                ("ask", "acc(0, 2)"),
+                ("bid", "acc(0, 1)"),
+                ("f", "let spread = &ask - &bid in spread / (spread + 1)"),
+            ],
+            Context::new(),
+        )
+        .unwrap();
+        assert!(intrp.node_map.contains_key("__let_spread_0"));
+        assert_eq!(intrp.root_nodes.len(), 2);
+        let result = intrp.compute_pass(&mut Vec::new()).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result["f"], Some(Type::Number(0.5)));
+
+        // the stateful function is shared rather than duplicated
+        let mut iit = Interpreter::new(&[("f", "let a = acc(0, 1) in a + a")], Context::new())
+            .unwrap()
+            .into_iter();
+        assert_eq!(iit.next().unwrap().unwrap()["f"], Some(Type::Number(2.0)));
+        assert_eq!(iit.next().unwrap().unwrap()["f"], Some(Type::Number(4.0)));
+    }
+
+    #[test]
+    pub fn test_arrays() {
+        let mut iit = Interpreter::new(
+            &[
+                ("fast", "acc(0, 1)"),
+                ("slow", "acc(0, 2)"),
+                ("mix", "dot([0.5, 0.25], [&fast, &slow])"),
+                ("pick", "[&fast, &slow, 10][1]"),
+            ],
+            Context::new(),
+        )
+        .unwrap()
+        .into_iter();
+        let result = iit.next().unwrap().unwrap();
+        assert_eq!(result["mix"], Some(Type::Number(1.0)));
+        assert_eq!(result["pick"], Some(Type::Number(2.0)));
+        let result = iit.next().unwrap().unwrap();
+        assert_eq!(result["mix"], Some(Type::Number(2.0)));
+
+        let intrp = Interpreter::new(&[("f", "dot([1], [1, 2])")], Context::new());
+        assert!(intrp
+            .err()
+            .unwrap()
+            .contains("arrays have different lengths: 1 and 2"));
+    }
+
+    #[test]
+    pub fn test_fields() {
+        let mut ctx = Context::new();
+        ctx.register_function("tick", Box::new(__tick));
+        let mut iit = Interpreter::new(
+            &[
+                ("last", "tick(10, 3)"),
+                ("value", "&last.price * &last.time"),
+            ],
+            ctx,
+        )
+        .unwrap()
+        .into_iter();
+        let result = iit.next().unwrap().unwrap();
+        assert_eq!(result["value"], Some(Type::Number(30.0)));
+
+        let mut ctx = Context::new();
+        ctx.register_function("tick", Box::new(__tick));
+        let intrp = Interpreter::new(&[("last", "tick(10, 3)"), ("v", "&last.volume")], ctx);
+        assert!(intrp.err().unwrap().contains("Unknown field 'volume'"));
+
+        let intrp = Interpreter::new(&[("n", "2 * 2"), ("v", "&n.price")], Context::new());
+        assert_eq!(
+            intrp.err().unwrap(),
+            "Type error in formula 'v'. Function price expects argument #1 to be TimeStep, but got Number"
+        );
+    }
+
+    #[test]
+    pub fn test_lambdas() {
+        let mut iit = Interpreter::new(
+            &[
+                ("n", "acc(0, 1)"),
+                ("sum", "reduce(0, |s, x| s + x, &n)"),
+                ("double", "map(|x| x * 2, [&n])"),
+                ("triple", "map(|x| x * 3, [&n])"),
+            ],
+            Context::new(),
+        )
+        .unwrap()
+        .into_iter();
+        let result = iit.next().unwrap().unwrap();
+        assert_eq!(result["sum"], Some(Type::Number(1.0)));
+        assert_eq!(result["double"], Some(Type::Array([2.0].into())));
+        assert_eq!(result["triple"], Some(Type::Array([3.0].into())));
+        iit.next();
+        let result = iit.next().unwrap().unwrap();
+        assert_eq!(result["sum"], Some(Type::Number(6.0)));
+
+        let intrp = Interpreter::new(
+            &[("n", "acc(0, 1)"), ("sum", "reduce(0, |s| s, &n)")],
+            Context::new(),
+        );
+        assert!(intrp
+            .err()
+            .unwrap()
+            .contains("function lambda expects 1 arguments, but recieves 2"));
+    }
+
+    #[test]
+    pub fn test_syntax_errors() {
+        let intrp = Interpreter::new(
+            &[("a", "2 + 2 )foo"), ("b", "1 + 1"), ("c", "ema(&x, 3) ema")],
+            Context::new(),
+        );
+        assert_eq!(
+            intrp.err().unwrap(),
+            "Failed to parse 2 formula(s):\n\
+             'a': unexpected input ')foo' at line 1, column 7 (did you mean to remove the unmatched ')'?)\n\
+             'c': unexpected input 'ema' at line 1, column 12 (did you mean to join the expressions with an operator like '+'?)"
+        );
+    }
+
+    #[test]
+    pub fn test_call_error_propagation() {
+        let mut ctx = Context::new();
+        ctx.register_function("fail", Box::new(__fail));
+        let intrp = Interpreter::new(&[("f1", "1 + fail(2)")], ctx).unwrap();
+        let err = intrp
+            .compute_pass(&mut intrp.root_nodes.clone())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Failed to evaluate formula 'f1'. Reason: fn fail(...) failed: cannot handle 2"
+        );
+        let mut iit = intrp.into_iter();
+        assert!(matches!(iit.next(), Some(Err(_))));
+    }
+
+    #[test]
+    pub fn test_upsert_formulas() {
+        let mut intrp = Interpreter::new(
+            &[("n", "acc(0, 1)"), ("m", "acc(0, 2)"), ("f", "&n * 10")],
+            Context::new(),
+        )
+        .unwrap();
+        let pass = |intrp: &Interpreter| intrp.compute_pass(&mut Vec::new()).unwrap();
+        assert_eq!(pass(&intrp)["f"], Some(Type::Number(10.0)));
+
+        // untouched formulas keep the state of their functions
+        intrp.upsert_formula("f", "&n + &m").unwrap();
+        assert_eq!(pass(&intrp)["f"], Some(Type::Number(6.0)));
+        intrp.upsert_formula("g", "&f * 2").unwrap();
+        let result = pass(&intrp);
+        assert_eq!(result["g"], Some(Type::Number(18.0)));
+        assert!(!result.contains_key("f"));
+
+        // rejected changes leave the interpreter as it was
+        let err = intrp.upsert_formula("n", "&g + 1").unwrap_err();
+        assert!(err.contains("dependancy cycle"), "{}", err);
+        let err = intrp.upsert_formula("h", "1 +").unwrap_err();
+        assert!(err.starts_with("Failed to parse formula 'h'"), "{}", err);
+        assert_eq!(
+            intrp.upsert_formula("h", "&missing"),
+            Err("Failed to find referant formula 'missing'".to_string())
+        );
+        for expression in ["acc(0, &missing)", "max(acc(0, 1), &missing)"] {
+            assert_eq!(
+                intrp.upsert_formula("h", expression),
+                Err("Failed to find referant formula 'missing'".to_string())
+            );
+        }
+        assert!(!intrp.node_map.contains_key("h"));
+        assert_eq!(pass(&intrp)["g"], Some(Type::Number(24.0)));
+
+        // only the changed formula and its dependents lose their results
+        intrp.upsert_formula("m", "acc(0, 3)").unwrap();
+        let result = |name: &str| intrp.node_map[name].borrow().result.clone();
+        assert_eq!(result("n"), Some(Type::Number(4.0)));
+        assert_eq!(result("m"), None);
+        assert_eq!(result("f"), None);
+        assert_eq!(result("g"), None);
+        assert_eq!(pass(&intrp)["g"], Some(Type::Number(16.0)));
+
+        // a failed formula doesn't shift names of hidden let-bindings
+        let err = intrp
+            .upsert_formula("h", "let s = acc(0, 1) in s + &missing")
+            .unwrap_err();
+        assert_eq!(err, "Failed to find referant formula 'missing'");
+        let err = intrp
+            .upsert_formula("h", "let s = acc(0, 1) in s + unknown(1)")
+            .unwrap_err();
+        assert!(err.contains("unknown"), "{}", err);
+        intrp
+            .upsert_formula("h", "let s = acc(0, 1) in s * s")
+            .unwrap();
+        assert!(intrp.node_map.contains_key("__let_s_0"));
+        intrp.remove_formula("h").unwrap();
+
+        assert_eq!(
+            intrp.remove_formula("f"),
+            Err("Formula 'f' is referenced by 'g'".to_string())
+        );
+        assert_eq!(
+            intrp.remove_formula("x"),
+            Err("Unknown formula 'x'".to_string())
+        );
+        intrp.remove_formula("g").unwrap();
+        intrp.remove_formula("f").unwrap();
+        let result = pass(&intrp);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result["n"], Some(Type::Number(6.0)));
+        assert_eq!(result["m"], Some(Type::Number(6.0)));
+    }
+
+    #[test]
+    pub fn test_upsert_relinks_whole_graph() {
+        let mut intrp = Interpreter::new(&[("n", "acc(0, 1) * 2")], Context::new()).unwrap();
+        let pass = |intrp: &Interpreter| intrp.compute_pass(&mut Vec::new()).unwrap();
+        assert_eq!(pass(&intrp)["n"], Some(Type::Number(2.0)));
+        // the call used by a single formula is inlined into it
+        assert!(!intrp.node_map.contains_key("__acc(0, 1)"));
+        let n = intrp.node_map["n"].clone();
+
+        // sharing the call reshapes `n`, though it doesn't depend on `h`
+        intrp.upsert_formula("h", "acc(0, 1) + 1").unwrap();
+        assert!(intrp.node_map.contains_key("__acc(0, 1)"));
+        assert!(!Rc::ptr_eq(&n, &intrp.node_map["n"]));
+        assert_eq!(intrp.node_map["n"].borrow().result, Some(Type::Number(2.0)));
+        let result = pass(&intrp);
+        assert_eq!(result["n"], Some(Type::Number(4.0)));
+        assert_eq!(result["h"], Some(Type::Number(3.0)));
+    }
+
+    #[test]
+    #[cfg(feature = "checkpoint")]
+    pub fn test_checkpoint() {
+        let formulas = [("n", "acc(0, 1)"), ("m", "acc(10, &n)"), ("f", "&n + &m")];
+        let pass = |intrp: &Interpreter| intrp.compute_pass(&mut Vec::new()).unwrap();
+        let intrp = Interpreter::new(&formulas, Context::new()).unwrap();
+        for _ in 0..3 {
+            pass(&intrp);
+        }
+        let bytes = intrp.checkpoint().unwrap();
+
+        // order of formulas doesn't matter
+        let mut restored =
+            Interpreter::new(&[formulas[2], formulas[0], formulas[1]], Context::new()).unwrap();
+        restored.restore(&bytes).unwrap();
+        let f = restored.node_map["f"].clone();
+        assert_eq!(f.borrow().result, intrp.node_map["f"].borrow().result);
+        assert!(f.borrow().result.is_some());
+        // the computation goes on as if there was no restart
+        for _ in 0..3 {
+            assert_eq!(pass(&restored), pass(&intrp));
+        }
+
+        let mut other = Interpreter::new(&[("n", "acc(0, 2)")], Context::new()).unwrap();
+        assert_eq!(
+            other.restore(&bytes),
+            Err("Checkpoint was made for another set of formulas".to_string())
+        );
+        let err = other.restore(&bytes[..4]).unwrap_err();
+        assert!(err.starts_with("Failed to decode checkpoint"), "{}", err);
+        assert_eq!(pass(&other)["n"], Some(Type::Number(2.0)));
+    }
+
+    #[test]
+    #[cfg(feature = "checkpoint")]
+    pub fn test_checkpoint_stateful_calls() {
+        let formulas = [("n", "acc(0, 1)"), ("sum", "reduce(0, |s, x| s + x, &n)")];
+        let pass = |intrp: &Interpreter| intrp.compute_pass(&mut Vec::new()).unwrap();
+        let intrp = Interpreter::new(&formulas, Context::new()).unwrap();
+        for _ in 0..3 {
+            pass(&intrp);
+        }
+        let mut restored = Interpreter::new(&formulas, Context::new()).unwrap();
+        restored.restore(&intrp.checkpoint().unwrap()).unwrap();
+        assert_eq!(pass(&restored)["sum"], Some(Type::Number(10.0)));
+
+        // lambdas are numbered by the context in the order they are lowered
+        let formulas = [
+            ("n", "acc(0, 1)"),
+            ("sum", "reduce(0, |s, x| s + x, &n)"),
+            ("acc", "map(|x| acc(0, x), [&n])"),
+        ];
+        let intrp = Interpreter::new(&formulas, Context::new()).unwrap();
+        for _ in 0..3 {
+            pass(&intrp);
+        }
+        let mut restored =
+            Interpreter::new(&[formulas[2], formulas[1], formulas[0]], Context::new()).unwrap();
+        assert!(restored
+            .upsert_formula("bad", "reduce(0, |s| s, &n)")
+            .is_err());
+        restored.restore(&intrp.checkpoint().unwrap()).unwrap();
+        for _ in 0..3 {
+            assert_eq!(pass(&restored), pass(&intrp));
+        }
+        assert_eq!(pass(&restored)["acc"], Some(Type::Array([28.0].into())));
+
+        let mut ctx = Context::new();
+        let mut calls = 0.0;
+        ctx.register_stateful_fn("count", move |x: f64| {
+            calls += 1.0;
+            x * calls
+        });
+        let intrp = Interpreter::new(&[("n", "count(1)")], ctx).unwrap();
+        assert_eq!(
+            intrp.checkpoint(),
+            Err("Failed to save state of 'n#0'. Reason: count can't save its state".to_string())
+        );
+    }
+
+    #[runtime_callable(pure)]
+    fn scale_num(x: f64, k: f64) -> f64 {
+        x * k
+    }
+
+    #[runtime_callable(pure)]
+    fn scale_arr(xs: &[f64], k: f64) -> Vec<f64> {
+        xs.iter().map(|x| x * k).collect()
+    }
+
+    #[test]
+    pub fn test_overloads_by_ref_kinds() {
+        let ctx = || {
+            let mut ctx = Context::new();
+            ctx.register_function("scale", Box::new(__scale_num));
+            ctx.register_function("scale", Box::new(__scale_arr));
+            ctx
+        };
+        // kinds of references are known once formulas are linked
+        let intrp = Interpreter::new(
+            &[
+                ("n", "acc(0, 1)"),
+                ("a", "[&n, 2]"),
+                ("sn", "scale(&n, 2)"),
+                ("sa", "scale(&a, 2)"),
+                ("sl", "map(|x| scale(x, 3), [1, 2])"),
+            ],
+            ctx(),
+        )
+        .unwrap();
+        let result = intrp.compute_pass(&mut Vec::new()).unwrap();
+        assert_eq!(result["sn"], Some(Type::Number(2.0)));
+        assert_eq!(result["sa"], Some(Type::Array([2.0, 4.0].into())));
+        assert_eq!(result["sl"], Some(Type::Array([3.0, 6.0].into())));
+
+        // a call inside of a lambda picks the overload again for other kinds
+        let candidates = vec![
+            Function::from_callable(Box::new(__scale_num)),
+            Function::from_callable(Box::new(__scale_arr)),
+        ];
+        let scale = Function::from_callable(Box::new(Overloaded::new("scale", candidates)));
+        let num = [Type::Number(2.0), Type::Number(3.0)];
+        assert_eq!(scale.call(&num), Ok(Some(Type::Number(6.0))));
+        let arr = [Type::Array([1.0, 2.0].into()), Type::Number(3.0)];
+        assert_eq!(scale.call(&arr), Ok(Some(Type::Array([3.0, 6.0].into()))));
+        assert_eq!(scale.call(&num), Ok(Some(Type::Number(6.0))));
+
+        let err = Interpreter::new(&[("s", "abc"), ("f", "scale(&s, 2)")], ctx())
+            .err()
+            .unwrap();
+        assert!(
+            err.ends_with(
+                "No overload of function scale accepts arguments of kinds (String, Number)"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    pub fn test_params() {
+        let mut ctx = Context::new();
+        ctx.register_param("k", Type::Number(2.0));
+        let intrp = Interpreter::new(
+            &[
+                ("n", "acc(0, 1)"),
+                ("scaled", "&n * $k"),
+                ("limit", "max($k * 4, 1)"),
+                ("weighted", "dot(map(|x| x * $k, [1, 2]), [1, 1])"),
+            ],
+            ctx,
+        )
+        .unwrap();
+        let limit = intrp.node_map["limit"].clone();
+        assert_eq!(limit.borrow().ast, IRNode::Value(Type::Number(8.0)));
+
+        let result = intrp.compute_pass(&mut Vec::new()).unwrap();
+        assert_eq!(result["scaled"], Some(Type::Number(2.0)));
+        assert_eq!(result["limit"], Some(Type::Number(8.0)));
+        assert_eq!(result["weighted"], Some(Type::Number(6.0)));
+
+        intrp.set_param("k", Type::Number(3.0)).unwrap();
+        assert_eq!(limit.borrow().ast, IRNode::Value(Type::Number(12.0)));
+        let result = intrp.compute_pass(&mut Vec::new()).unwrap();
+        // the state of acc is kept
+        assert_eq!(result["scaled"], Some(Type::Number(6.0)));
+        assert_eq!(result["limit"], Some(Type::Number(12.0)));
+        // lambdas read the parameter on every call
+        intrp.set_param("k", Type::Number(10.0)).unwrap();
+        let result = intrp.compute_pass(&mut Vec::new()).unwrap();
+        assert_eq!(result["weighted"], Some(Type::Number(30.0)));
+
+        assert_eq!(
+            intrp.set_param("k", Type::String("x".to_string())),
+            Err("Parameter $k expects Number, but got String".to_string())
+        );
+        assert_eq!(
+            intrp.set_param("j", Type::Number(1.0)),
+            Err("Unknown parameter $j".to_string())
+        );
+        assert_eq!(
+            Interpreter::new(&[("f", "$j + 1")], Context::new()).err(),
+            Some("Unknown parameter $j".to_string())
+        );
+    }
+
+    #[test]
+    pub fn test_acc_unsupported_type() {
+        let err = Interpreter::new(&[("f1", "acc(0, unknown)")], Context::new())
+            .err()
+            .unwrap();
+        assert!(
+            err.contains("Function acc expects argument #2 to be Number, but got String"),
+            "{}",
+            err
+        );
+        // the state is constructed from constants only
+        let err = Interpreter::new(&[("n", "acc(0, 1)"), ("f1", "acc(&n, 1)")], Context::new())
+            .err()
+            .unwrap();
+        assert!(
+            err.contains("leading arguments must be known at build time"),
+            "{}",
+            err
+        );
+    }
 }
@@ -1,9 +1,11 @@
+use crate::builtin::{self, Closure, FieldAccess, Lambda, LambdaArg};
 use crate::formula::SharedFormula;
 use express::{
-    lang::ast::Operation,
+    lang::ast::{Expression, Literal, Operation},
     types::{Function as Func, Type},
 };
 use std::{
+    cell::RefCell,
     fmt::{Debug, Display},
     rc::Rc,
 };
@@ -23,11 +25,7 @@ impl FormulaLink {
     }
 
     pub fn link(&self) -> Option<SharedFormula> {
-        if let Some(l) = &self.link {
-            Some(l.clone())
-        } else {
-            None
-        }
+        self.link.clone()
     }
 
     /// Tells how many times a link was shared
@@ -49,17 +47,172 @@ impl FormulaLink {
     }
 }
 
+/// Value of a runtime-tunable parameter `$name`. Every use of
+/// the parameter shares the same slot, so a new value is seen
+/// by all formulas at once.
+#[derive(Debug, Clone)]
+pub struct ParamSlot {
+    pub name: String,
+    value: Rc<RefCell<Type>>,
+}
+
+impl ParamSlot {
+    pub fn new(name: &str, default: Type) -> Self {
+        Self {
+            name: name.to_string(),
+            value: Rc::new(RefCell::new(default)),
+        }
+    }
+
+    pub fn get(&self) -> Type {
+        self.value.borrow().clone()
+    }
+
+    /// Replaces the value. Its kind may not change, since formulas
+    /// were type checked against the default one.
+    pub fn set(&self, value: Type) -> Result<(), String> {
+        let kind = self.value.borrow().kind();
+        if value.kind() != kind {
+            return Err(format!(
+                "Parameter ${} expects {}, but got {}",
+                self.name,
+                kind,
+                value.kind()
+            ));
+        }
+        self.value.replace(value);
+        Ok(())
+    }
+}
+
+impl PartialEq for ParamSlot {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.value, &other.value)
+    }
+}
+
 #[derive(Clone)]
 pub enum IRNode {
     Value(Type),
     // NOTE(iy): Pointer primitive requires changes when adopting
     // a parallel execution model (Something like RWLock?).
     Ref(FormulaLink),
+    /// Parameter that is never folded at lowering
+    Param(ParamSlot),
     Function(Func, Vec<IRNode>),
     BinOp(Box<IRNode>, Box<IRNode>, Operation),
     UnOp(Box<IRNode>, Operation),
 }
 
+impl IRNode {
+    /// Tells if the node refers to a formula with the given name
+    pub fn references(&self, name: &str) -> bool {
+        match self {
+            IRNode::Value(_) | IRNode::Param(_) => false,
+            IRNode::Ref(r) => r.name == name,
+            IRNode::Function(_, args) => args.iter().any(|arg| arg.references(name)),
+            IRNode::BinOp(lhs, rhs, _) => lhs.references(name) || rhs.references(name),
+            IRNode::UnOp(rhs, _) => rhs.references(name),
+        }
+    }
+
+    /// Tells if the node reads the parameter with the given name
+    pub fn uses_param(&self, name: &str) -> bool {
+        match self {
+            IRNode::Value(_) | IRNode::Ref(_) => false,
+            IRNode::Param(p) => p.name == name,
+            IRNode::Function(_, args) => args.iter().any(|arg| arg.uses_param(name)),
+            IRNode::BinOp(lhs, rhs, _) => lhs.uses_param(name) || rhs.uses_param(name),
+            IRNode::UnOp(rhs, _) => rhs.uses_param(name),
+        }
+    }
+
+    /// Tells if the node reads any parameter
+    pub fn has_params(&self) -> bool {
+        match self {
+            IRNode::Value(_) | IRNode::Ref(_) => false,
+            IRNode::Param(_) => true,
+            IRNode::Function(_, args) => args.iter().any(IRNode::has_params),
+            IRNode::BinOp(lhs, rhs, _) => lhs.has_params() || rhs.has_params(),
+            IRNode::UnOp(rhs, _) => rhs.has_params(),
+        }
+    }
+
+    /// Converts the node back into an expression, so that it can be printed
+    /// in the formula syntax. Values that have no literal, like time steps,
+    /// are kept as identifiers of their textual form.
+    pub fn to_expression(&self) -> Expression {
+        match self {
+            IRNode::Value(Type::Number(num)) => Expression::Const(Literal::Number(*num)),
+            IRNode::Value(Type::String(string)) => Expression::Const(Literal::Str(string.clone())),
+            IRNode::Value(Type::Array(items)) => Expression::Array(
+                items
+                    .iter()
+                    .map(|item| Expression::Const(Literal::Number(*item)))
+                    .collect(),
+            ),
+            IRNode::Value(Type::Function(func)) => match func.downcast_ref::<Lambda>() {
+                Some(lambda) => lambda.to_expression(),
+                None => Expression::Const(Literal::Ident(func.name().to_string())),
+            },
+            // series and records only get here by folding results of formulas
+            IRNode::Value(val) => Expression::Const(Literal::Ident(val.to_string())),
+            IRNode::Ref(r) => Expression::Const(Literal::Ref(r.name.clone())),
+            IRNode::Param(p) => Expression::Const(Literal::Param(p.name.clone())),
+            IRNode::Function(func, args) => Self::call_expression(func, args),
+            IRNode::BinOp(lhs, rhs, op) => Expression::BinOp(
+                Box::new(lhs.to_expression()),
+                Box::new(rhs.to_expression()),
+                *op,
+            ),
+            IRNode::UnOp(rhs, op) => Expression::UnOp(*op, Box::new(rhs.to_expression())),
+        }
+    }
+
+    /// Maps calls of builtins back to the syntax they are lowered from
+    fn call_expression(func: &Func, args: &[IRNode]) -> Expression {
+        let mut args = args.iter().map(IRNode::to_expression);
+        if func.downcast_ref::<builtin::__array>().is_some() {
+            return Expression::Array(args.collect());
+        }
+        if func.downcast_ref::<builtin::__index>().is_some() {
+            if let (Some(arr), Some(idx)) = (args.next(), args.next()) {
+                return Expression::Index(Box::new(arr), Box::new(idx));
+            }
+        }
+        if func.downcast_ref::<FieldAccess>().is_some() {
+            if let Some(base) = args.next() {
+                return Expression::Field(Box::new(base), func.name().to_string());
+            }
+        }
+        if func.downcast_ref::<LambdaArg>().is_some()
+            || func.downcast_ref::<builtin::Constant>().is_some()
+        {
+            return Expression::Const(Literal::Ident(func.name().to_string()));
+        }
+        if let Some(closure) = func.downcast_ref::<Closure>() {
+            if let Some(lambda) = closure.lambda().downcast_ref::<Lambda>() {
+                return lambda.to_expression();
+            }
+        }
+        Expression::Function {
+            name: Literal::Ident(func.name().to_string()),
+            args: args.collect(),
+        }
+    }
+
+    /// Returns the name of the first formula the node refers to
+    pub fn first_ref(&self) -> Option<&str> {
+        match self {
+            IRNode::Value(_) | IRNode::Param(_) => None,
+            IRNode::Ref(r) => Some(r.name.as_str()),
+            IRNode::Function(_, args) => args.iter().find_map(|arg| arg.first_ref()),
+            IRNode::BinOp(lhs, rhs, _) => lhs.first_ref().or_else(|| rhs.first_ref()),
+            IRNode::UnOp(rhs, _) => rhs.first_ref(),
+        }
+    }
+}
+
 impl PartialEq for IRNode {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -68,6 +221,7 @@ impl PartialEq for IRNode {
             (Self::BinOp(l0, l1, l2), Self::BinOp(r0, r1, r2)) => l0 == r0 && l1 == r1 && l2 == r2,
             (Self::UnOp(l0, l1), Self::UnOp(r0, r1)) => l0 == r0 && l1 == r1,
             (Self::Ref(l), Self::Ref(r)) => l == r,
+            (Self::Param(l), Self::Param(r)) => l == r,
             _ => false,
         }
     }
@@ -88,25 +242,14 @@ impl Debug for IRNode {
                 .finish(),
             Self::UnOp(arg0, arg1) => f.debug_tuple("UnOp").field(arg0).field(arg1).finish(),
             Self::Ref(r) => f.debug_tuple("Ref").field(&r.name).finish(),
+            Self::Param(p) => f.debug_tuple("Param").field(&p.name).finish(),
         }
     }
 }
 
 impl Display for IRNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            IRNode::Value(val) => write!(f, "{}", val),
-            IRNode::Ref(r) => write!(f, "&{}", r.name),
-            IRNode::Function(func, args) => {
-                write!(f, "{}(", func.name())?;
-                args.iter().for_each(|arg| {
-                    write!(f, "{},", arg).unwrap();
-                });
-                write!(f, ")")
-            }
-            IRNode::BinOp(lhs, rhs, op) => write!(f, "{}{}{}", lhs, op, rhs),
-            IRNode::UnOp(lhs, op) => write!(f, "{}{}", op, lhs),
-        }
+        write!(f, "{}", self.to_expression())
     }
 }
 
@@ -1,14 +1,18 @@
+pub mod builtin;
+#[cfg(feature = "checkpoint")]
+pub mod checkpoint;
 pub mod ctx;
 pub mod dag;
 pub mod formula;
 pub mod interp;
 pub mod ir;
+pub mod typeck;
 
 #[cfg(test)]
+#[allow(clippy::approx_constant)]
 mod test {
     use crate::ctx::Context;
     use express::prelude::*;
-    use express_std;
 
     #[runtime_callable]
     fn foo() -> Option<f64> {
@@ -32,8 +36,14 @@ mod test {
         };
 
         assert!(!ctx.ns_const.is_empty());
-        assert_eq!(ctx.find_constant("PI").unwrap(), express_std::math::PI);
-        assert_eq!(ctx.find_constant("EPS").unwrap(), express_std::math::EPS);
+        assert_eq!(
+            ctx.find_constant("PI"),
+            Some(&Type::Number(express_std::math::PI))
+        );
+        assert_eq!(
+            ctx.find_constant("EPS"),
+            Some(&Type::Number(express_std::math::EPS))
+        );
 
         assert!(!ctx.ns_fn.is_empty());
         assert_eq!(
@@ -42,20 +52,128 @@ mod test {
                     .unwrap()
                     .call(&[Type::Number(2.0)])
                     .unwrap()
+                    .unwrap()
             ),
             express_std::math::ln(2.0).unwrap()
         )
     }
 
-    //#[test]
-    //fn test_prefixed_import() {
-    //    let mut ctx = Context::new();
-    //    use_library! {
-    //        context ctx;
-    //        library express_std;
-    //        functions {
-    //            math::log::log;
-    //        }
-    //    }
-    //}
+    #[test]
+    fn test_qualified_import() {
+        let mut ctx = Context::new();
+        use_library! {
+            context ctx;
+            library express_std;
+            constants {
+                math::PI;
+                use math::TAU;
+            }
+            functions {
+                math::log;
+                use math::{ln, common::max};
+            }
+        };
+
+        assert!(ctx.find_constant("math::PI").is_some());
+        assert!(ctx.find_constant("PI").is_none());
+        assert!(ctx.find_constant("math::TAU").is_some());
+        assert!(ctx.find_constant("TAU").is_some());
+        assert!(ctx.find_function("math::log").is_some());
+        assert!(ctx.find_function("log").is_none());
+        assert!(ctx.find_function("ln").is_some());
+        assert!(ctx.find_function("math::common::max").is_some());
+        assert!(ctx.find_function("max").is_some());
+    }
+
+    mod defaults {
+        pub const SYMBOL: &str = "BTCUSD";
+        pub const WEIGHTS: [f64; 2] = [0.2, 0.8];
+    }
+
+    #[test]
+    fn test_typed_constants() {
+        let mut ctx = Context::new();
+        use_library! {
+            context ctx;
+            library self::defaults;
+            constants {
+                use SYMBOL;
+                WEIGHTS as weights;
+            }
+        };
+
+        assert_eq!(
+            ctx.find_constant("SYMBOL"),
+            Some(&Type::String("BTCUSD".to_string()))
+        );
+        assert_eq!(ctx.find_constant("weights"), Some(&Type::from([0.2, 0.8])));
+    }
+
+    #[test]
+    fn test_module_import() {
+        let mut ctx = Context::new();
+        use_library! {
+            context ctx;
+            library express_std;
+            constants {}
+            functions {
+                timeseries::*;
+                func::* as fp;
+                use math::*;
+            }
+        };
+
+        assert!(ctx.find_function("timeseries::ema::ema").is_some());
+        assert!(ctx.find_function("timeseries::ema").is_none());
+        assert!(ctx.find_function("ema").is_none());
+        assert!(ctx.find_function("fp::reduce::reduce").is_some());
+        assert!(ctx.find_function("func::reduce::reduce").is_none());
+        // callables of submodules are registered by their module
+        assert!(ctx.find_function("math::common::max").is_some());
+        assert!(ctx.find_function("math::log::log").is_some());
+        assert!(ctx.find_function("math::max").is_none());
+        assert!(ctx.find_function("max").is_some());
+
+        assert!(ctx.register_module("express_std::func").is_ok());
+        assert!(ctx.find_function("func::acc::acc").is_some());
+        // registering the same callables again replaces them
+        assert!(ctx.register_module_as("express_std::math", "").is_ok());
+        assert!(ctx.import_module("express_std::math", "").is_ok());
+        assert_eq!(
+            ctx.register_module("express_std::nothing"),
+            Err("Module 'express_std::nothing' has no exported callables".to_string())
+        );
+    }
+
+    #[test]
+    fn test_prefixed_import() {
+        let mut ctx = Context::new();
+        use_library! {
+            context ctx;
+            library express_std;
+            prefix "std_";
+            constants {
+                math::PI as pi;
+                use math::{EPS as e, TAU};
+            }
+            functions {
+                math::log::log;
+                math::common::max as math::max;
+                use timeseries::ema as smooth;
+            }
+        }
+
+        assert!(ctx.find_constant("std_pi").is_some());
+        assert!(ctx.find_constant("std_math::PI").is_none());
+        assert!(ctx.find_constant("std_math::EPS").is_some());
+        assert!(ctx.find_constant("std_e").is_some());
+        assert!(ctx.find_constant("std_TAU").is_some());
+        assert!(ctx.find_constant("TAU").is_none());
+        assert!(ctx.find_function("std_math::log::log").is_some());
+        assert!(ctx.find_function("std_math::max").is_some());
+        assert!(ctx.find_function("std_math::common::max").is_none());
+        assert!(ctx.find_function("std_timeseries::ema").is_some());
+        assert!(ctx.find_function("std_smooth").is_some());
+        assert!(ctx.find_function("ema").is_none());
+    }
 }
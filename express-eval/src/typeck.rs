@@ -0,0 +1,183 @@
+use crate::builtin::Overloaded;
+use crate::formula::SharedFormula;
+use crate::ir::IRNode;
+use express::{
+    lang::ast::Operation,
+    types::{Function, Kind, Signature},
+};
+use std::collections::BTreeMap;
+
+/// Returns the kind of a node without looking through references.
+/// Referenced formulas are not known during lowering, so they are `Any`.
+pub fn kind_of(node: &IRNode) -> Kind {
+    match node {
+        IRNode::Value(val) => val.kind(),
+        IRNode::Param(p) => p.get().kind(),
+        IRNode::Ref(_) => Kind::Any,
+        IRNode::Function(f, _) => f.signature().returns,
+        IRNode::BinOp(..) | IRNode::UnOp(..) => Kind::Number,
+    }
+}
+
+/// Checks argument kinds against the function signature
+pub fn check_call(f: &Function, args: &[Kind]) -> Result<(), String> {
+    let signature = f.signature();
+    for (pos, got) in args.iter().enumerate() {
+        let expected = signature.kind_at(pos).unwrap_or(Kind::Any);
+        if !expected.accepts(*got) {
+            return Err(format!(
+                "Function {} expects argument #{} to be {}, but got {}",
+                f.name(),
+                pos + 1,
+                expected,
+                got
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Counts arguments whose kinds are known and match the signature exactly
+pub fn exact_matches(signature: &Signature, args: &[Kind]) -> usize {
+    args.iter()
+        .enumerate()
+        .filter(|(pos, got)| **got != Kind::Any && signature.kind_at(*pos) == Some(**got))
+        .count()
+}
+
+/// Returns positions of the overloads that accept arguments of given kinds
+/// and match the most of them exactly. Kinds are given per overload, since
+/// named arguments are bound to parameters of each one differently.
+pub fn best_overloads<'a>(
+    candidates: impl IntoIterator<Item = (&'a Function, &'a [Kind])>,
+) -> Vec<usize> {
+    let mut best = Vec::new();
+    let mut top = 0;
+    for (pos, (f, kinds)) in candidates.into_iter().enumerate() {
+        if check_call(f, kinds).is_err() {
+            continue;
+        }
+        let score = exact_matches(&f.signature(), kinds);
+        if best.is_empty() || score > top {
+            best = vec![pos];
+            top = score;
+        } else if score == top {
+            best.push(pos);
+        }
+    }
+    best
+}
+
+/// Picks the overload that accepts arguments of given kinds and matches
+/// the most of them exactly. A tie is reported as an ambiguity.
+/// Returns the position of the overload.
+pub fn pick_overload(name: &str, overloads: &[Function], kinds: &[Kind]) -> Result<usize, String> {
+    match best_overloads(overloads.iter().map(|f| (f, kinds))).as_slice() {
+        [pos] => Ok(*pos),
+        best => Err(overload_error(name, best.len(), kinds)),
+    }
+}
+
+/// Reports that none or several overloads accept arguments of the kinds
+pub fn overload_error(name: &str, matching: usize, kinds: &[Kind]) -> String {
+    if matching == 0 {
+        format!(
+            "No overload of function {} accepts arguments of kinds ({})",
+            name,
+            format_kinds(kinds)
+        )
+    } else {
+        format!(
+            "Ambiguous call to function {}. {} overloads accept arguments of kinds ({})",
+            name,
+            matching,
+            format_kinds(kinds)
+        )
+    }
+}
+
+pub fn format_kinds(kinds: &[Kind]) -> String {
+    kinds
+        .iter()
+        .map(|k| k.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Checks that operands of an arithmetic operation are numbers
+pub fn check_operands(op: Operation, args: &[Kind]) -> Result<(), String> {
+    match args.iter().find(|k| !Kind::Number.accepts(**k)) {
+        Some(got) => Err(format!(
+            "Operator '{}' expects Number operands, but got {}",
+            op, got
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Infers result kinds of linked formulas and rejects ill-typed ones.
+/// References are followed through formula links, so it must be run
+/// after the DAG is built and checked for cycles.
+#[derive(Default)]
+pub struct TypeChecker {
+    known: BTreeMap<String, Kind>,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks every formula of the graph
+    pub fn check_all(&mut self, node_map: &BTreeMap<String, SharedFormula>) -> Result<(), String> {
+        for formula in node_map.values() {
+            self.infer_formula(formula)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the result kind of a formula
+    pub fn infer_formula(&mut self, formula: &SharedFormula) -> Result<Kind, String> {
+        let formula = formula.borrow();
+        if let Some(kind) = self.known.get(&formula.name) {
+            return Ok(*kind);
+        }
+        let kind = self
+            .infer(&formula.ast)
+            .map_err(|e| format!("Type error in formula '{}'. {}", formula.name, e))?;
+        self.known.insert(formula.name.clone(), kind);
+        Ok(kind)
+    }
+
+    fn infer(&mut self, node: &IRNode) -> Result<Kind, String> {
+        match node {
+            IRNode::Value(val) => Ok(val.kind()),
+            IRNode::Param(p) => Ok(p.get().kind()),
+            IRNode::Ref(link) => match link.link() {
+                Some(formula) => self.infer_formula(&formula),
+                None => Ok(Kind::Any),
+            },
+            IRNode::Function(f, args) => {
+                let kinds = self.infer_all(args)?;
+                if let Some(overloaded) = f.downcast_ref::<Overloaded>() {
+                    return overloaded.resolve(&kinds, args);
+                }
+                check_call(f, &kinds)?;
+                Ok(f.signature().returns)
+            }
+            IRNode::BinOp(lhs, rhs, op) => {
+                let kinds = [self.infer(lhs)?, self.infer(rhs)?];
+                check_operands(*op, &kinds)?;
+                Ok(Kind::Number)
+            }
+            IRNode::UnOp(rhs, op) => {
+                check_operands(*op, &[self.infer(rhs)?])?;
+                Ok(Kind::Number)
+            }
+        }
+    }
+
+    fn infer_all(&mut self, nodes: &[IRNode]) -> Result<Vec<Kind>, String> {
+        nodes.iter().map(|node| self.infer(node)).collect()
+    }
+}
@@ -1,39 +1,29 @@
 use express::prelude::*;
+#[cfg(feature = "checkpoint")]
+use serde::{Deserialize, Serialize};
 
-/// This is how **you define a constructor**:
-/// Note that it is also handy to get a gist of function signature.
-/// This signature will be employed in the real `call` method as `args: &[Type]`.
-/// You can think of it as currying. But on each call you recieve all arguments.
+/// Running sum of the values: `acc(0, &px)`.
+/// The state is constructed at build time from the leading `init` argument.
+/// Later on `step` recieves the rest of the arguments on each call.
 /// Like yeah, currying in compile time is object construction. How. Cool. Is. That?!
-#[runtime_callable(constant)]
-pub fn acc(init: f64, _f: &Type) -> Accumulate {
-    Accumulate { acc: init }
-}
-
-/// The real function state
+/// With the `checkpoint` feature the running sum survives a restart
+/// via interpreter checkpoints.
+#[cfg_attr(feature = "checkpoint", derive(Serialize, Deserialize))]
 pub struct Accumulate {
     acc: f64,
 }
 
-impl Callable for Accumulate {
-    #[inline(always)]
-    fn name(&self) -> &'static str {
-        "Accum"
+#[cfg_attr(feature = "checkpoint", runtime_callable(stateful, checkpoint, export))]
+#[cfg_attr(not(feature = "checkpoint"), runtime_callable(stateful, export))]
+impl Accumulate {
+    /// This is how **you define a constructor**
+    pub fn acc(init: f64) -> Self {
+        Accumulate { acc: init }
     }
 
     #[inline]
-    fn call(&mut self, args: &[Type]) -> Option<Type> {
-        let arg = args[1].clone();
-        match arg {
-            Type::Number(num) => self.acc += num,
-            Type::None => return None,
-            t @ _ => panic!("Acc function recieved unsupported type: {t}"),
-        }
-        Some(self.acc.into())
-    }
-
-    #[inline(always)]
-    fn argcnt(&self) -> usize {
-        2
+    fn step(&mut self, value: f64) -> f64 {
+        self.acc += value;
+        self.acc
     }
 }
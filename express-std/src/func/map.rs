@@ -0,0 +1,41 @@
+use express::prelude::*;
+
+/// Calls the function with a single number and expects a number back
+fn apply(func: &Function, value: f64) -> Result<f64, String> {
+    if !func.arity().accepts(1) {
+        return Err(format!(
+            "function {} expects {} arguments, but recieves 1",
+            func.name(),
+            func.arity()
+        ));
+    }
+    match func.call(&[Type::Number(value)]) {
+        Ok(Some(Type::Number(result))) => Ok(result),
+        Ok(Some(t)) => Err(format!(
+            "function {} returned {}, but expected Number",
+            func.name(),
+            t
+        )),
+        Ok(None) => Err(format!("function {} returned no value", func.name())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Applies the function to every element: `map(|x| x * 2, [1, 2])`
+#[runtime_callable(pure, export)]
+pub fn map(func: Function, items: &[f64]) -> Result<Vec<f64>, String> {
+    items.iter().map(|item| apply(&func, *item)).collect()
+}
+
+/// Keeps elements the function returns a non-zero number for:
+/// `filter(|x| x - 1, [1, 2, 3])` gives `[2, 3]`
+#[runtime_callable(pure, export)]
+pub fn filter(func: Function, items: &[f64]) -> Result<Vec<f64>, String> {
+    let mut kept = Vec::with_capacity(items.len());
+    for item in items {
+        if apply(&func, *item)? != 0.0 {
+            kept.push(*item);
+        }
+    }
+    Ok(kept)
+}
@@ -1,4 +1,7 @@
 mod acc;
+mod map;
 pub mod reduce;
 
 pub use acc::*;
+pub use map::*;
+pub use reduce::*;
@@ -1,31 +1,26 @@
 use express::prelude::*;
 
-// TODO: Need to support variadic arguments
-// Need to support implicit ctx lookup
-
-// #[runtime_callable(constant)]
-// fn reduce(state: f64, func: String) -> Reduce {
-//     Reduce { state, func }
-// }
+/// Folds arguments of every call into a state with the given function:
+/// `reduce(0, |acc, x| acc + x, &px)`. The function recieves the state
+/// followed by the rest of arguments and returns the new state.
+#[runtime_callable(constant, export)]
+pub fn reduce(init: f64, func: Function, args: &[Type]) -> Result<Reduce, String> {
+    if !func.arity().accepts(args.len() + 1) {
+        return Err(format!(
+            "function {} expects {} arguments, but recieves {}",
+            func.name(),
+            func.arity(),
+            args.len() + 1
+        ));
+    }
+    Ok(Reduce { state: init, func })
+}
 
-struct Reduce {
+/// The real function state
+pub struct Reduce {
     state: f64,
     func: Function,
 }
-// fn signature: reduce(0, fn, arg*)
-// 1. f64 -- initial state
-// 2. expr -- an expression which will be called
-// 3. arg -- arguments to the expr(2)
-// fn init(args: &[Type], ctx: &dyn InterpreterContext) {
-//   self.state = args[0].clone().into();
-//   let fname: String = args[1].clone().into();
-//   if let Some(f) = ctx.find_function(fname.as_str()) {
-//       self.func = f.clone();
-//   } else {
-//       panic!("Failed to find function '{fname}' in the context");
-//   }
-//}
-// reduce(0, add, 1, 1)
 
 impl Callable for Reduce {
     #[inline]
@@ -34,23 +29,62 @@ impl Callable for Reduce {
     }
 
     #[inline]
-    fn call(&mut self, args: &[Type]) -> Option<Type> {
-        let args = &args[2..];
-        if args.len() != self.func.argcnt() {
-            panic!(
-                "Function {} recieved {} arguments, but expects {}",
-                self.func.name(),
-                args.len(),
-                self.func.argcnt()
-            );
+    fn call(&mut self, args: &[Type]) -> CallResult {
+        let mut call_args = Vec::with_capacity(args.len() - 1);
+        call_args.push(Type::Number(self.state));
+        call_args.extend_from_slice(&args[2..]);
+        match self.func.call(&call_args)? {
+            Some(Type::Number(state)) => {
+                self.state = state;
+                Ok(Some(Type::Number(state)))
+            }
+            Some(t) => Err(CallError::new(
+                self.name(),
+                format!(
+                    "function {} returned {}, but expected Number",
+                    self.func.name(),
+                    t
+                ),
+            )),
+            None => Ok(None),
         }
-        let state = self.func.call(&args[2..])?;
-        self.state = state.clone().into();
-        Some(state)
     }
 
     #[inline]
-    fn argcnt(&self) -> usize {
-        3
+    fn arity(&self) -> Arity {
+        Arity::variadic(2)
+    }
+
+    fn signature(&self) -> Signature {
+        Signature {
+            args: vec![Kind::Number, Kind::Function],
+            rest: Some(Kind::Any),
+            params: Vec::new(),
+            returns: Kind::Number,
+        }
+    }
+
+    /// Saves the folded value along with the state of a stateful function
+    #[cfg(feature = "checkpoint")]
+    fn save_state(&self) -> Result<Option<Vec<u8>>, String> {
+        let func = if self.func.can_be_optimized() {
+            None
+        } else {
+            match self.func.save_state()? {
+                Some(state) => Some(state),
+                None => return Ok(None),
+            }
+        };
+        encode_state(&(self.state, func)).map(Some)
+    }
+
+    #[cfg(feature = "checkpoint")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let (state, func): (f64, Option<Vec<u8>>) = decode_state(state)?;
+        if let Some(func) = func {
+            self.func.load_state(&func)?;
+        }
+        self.state = state;
+        Ok(())
     }
 }
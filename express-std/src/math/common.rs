@@ -1,11 +1,26 @@
 use express::prelude::*;
 
-#[runtime_callable(pure)]
-fn max(lhs: f64, rhs: f64) -> f64 {
-    lhs.max(rhs)
+/// Returns the largest of the given values: `max(a, b, c, ...)`
+#[runtime_callable(pure, export)]
+fn max(first: f64, #[variadic] rest: Vec<f64>) -> f64 {
+    rest.into_iter().fold(first, f64::max)
 }
 
-#[runtime_callable(pure)]
-fn min(lhs: f64, rhs: f64) -> f64 {
-    lhs.min(rhs)
+/// Returns the smallest of the given values: `min(a, b, c, ...)`
+#[runtime_callable(pure, export)]
+fn min(first: f64, #[variadic] rest: Vec<f64>) -> f64 {
+    rest.into_iter().fold(first, f64::min)
+}
+
+/// Weighted sum of values: `dot([0.2, 0.8], [&fast, &slow])`
+#[runtime_callable(pure, export)]
+fn dot(weights: &[f64], values: &[f64]) -> Result<f64, String> {
+    if weights.len() != values.len() {
+        return Err(format!(
+            "arrays have different lengths: {} and {}",
+            weights.len(),
+            values.len()
+        ));
+    }
+    Ok(weights.iter().zip(values).map(|(w, v)| w * v).sum())
 }
@@ -12,7 +12,7 @@ Computes exponential moving average over arbitrary slice
  */
 #[allow(dead_code)]
 #[inline]
-#[runtime_callable(pure)]
+#[runtime_callable(pure, export)]
 pub fn ema(ts_buffer: TimeSeries, lookback: f64) -> Option<f64> {
     let mut prev_tick = ts_buffer.last()?;
     if lookback > (prev_tick.time - ts_buffer.first()?.time) {
@@ -42,7 +42,7 @@ pub fn ema(ts_buffer: TimeSeries, lookback: f64) -> Option<f64> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{fill_ts, timeseries::TimeStep};
+    use crate::timeseries::TimeStep;
     use float_cmp::assert_approx_eq;
     use std::sync::Arc;
 
@@ -34,8 +34,9 @@ const JMA_SUMLEN: usize = 10;
 const JMA_BANDPERIOD: usize = 65;
 
 /// Jurik Moving Average (JMA)
-#[runtime_callable(pure)]
-fn jma(ts: TimeSeries, len: usize, phase: f64) -> Option<f64> {
+/// Usage: `jma(&px, len=20, phase=0)`
+#[runtime_callable(pure, export)]
+fn jma(ts: TimeSeries, #[default = 7] len: usize, #[default = 0.0] phase: f64) -> Option<f64> {
     // setup jma variables
     // Boooooooy it is gonna be slow to compute...
     if ts.is_empty() || len > ts.len() {
@@ -65,9 +66,10 @@ fn jma(ts: TimeSeries, len: usize, phase: f64) -> Option<f64> {
 
         // relative price volatility factor
         vsum.push(
-            vsum[idx - 1] + (volty[idx] - volty[(idx - JMA_SUMLEN).max(0)]) / JMA_SUMLEN as f64,
+            vsum[idx - 1]
+                + (volty[idx] - volty[idx.saturating_sub(JMA_SUMLEN)]) / JMA_SUMLEN as f64,
         );
-        let avgvol_slice = &vsum[(idx - JMA_BANDPERIOD).max(0)..=idx];
+        let avgvol_slice = &vsum[idx.saturating_sub(JMA_BANDPERIOD)..=idx];
         let avg_volty: f64 = avgvol_slice.iter().sum::<f64>() / avgvol_slice.len() as f64;
         let d_volty = if avg_volty == 0.0 {
             0.0
@@ -103,7 +105,7 @@ fn jma(ts: TimeSeries, len: usize, phase: f64) -> Option<f64> {
 
         // 3rd stage - final smoothing by unique Jurik adaptive filter
         det1 = ((ma2 - jma) * (1.0 - alpha).powf(2.0)) + (alpha.powf(2.0) * det1);
-        jma = jma + det1;
+        jma += det1;
     }
     Some(jma)
 }
@@ -11,7 +11,7 @@ Computes simple moving average over arbitrary slice of md
 ![MA formula](https://wikimedia.org/api/rest_v1/media/math/render/svg/a608544726b8de1c3de562245ff0d1cd3d0efad6)
  */
 #[allow(dead_code)]
-#[runtime_callable(pure)]
+#[runtime_callable(pure, export)]
 pub fn ma(ts_buffer: TimeSeries, lookback: f64) -> Option<f64> {
     let last_tick = ts_buffer.last()?.time;
     // NOTE(iy): Should this case be cumultive in behavor? E.g. CMA
@@ -39,7 +39,6 @@ mod test {
     use std::sync::Arc;
 
     use super::ma;
-    use crate::fill_ts;
     use crate::timeseries::TimeStep;
 
     #[test]
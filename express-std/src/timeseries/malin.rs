@@ -13,7 +13,7 @@ Computes linear moving average over arbitrary slice of md
  */
 #[allow(dead_code)]
 #[inline]
-#[runtime_callable(pure)]
+#[runtime_callable(pure, export)]
 pub fn malin(ts_buffer: TimeSeries, lookback: f64) -> Option<f64> {
     let last_tick = ts_buffer.last()?;
     // NOTE(iy): Should this case be cumultive in behavor? E.g. CMA
@@ -42,7 +42,6 @@ mod test {
     use express::types::TimeStep;
 
     use super::*;
-    use crate::fill_ts;
     use std::sync::Arc;
 
     #[test]
@@ -13,7 +13,7 @@ Computes Time weighted average over arbitrary slice of md
  */
 #[allow(dead_code)]
 #[inline]
-#[runtime_callable(pure)]
+#[runtime_callable(pure, export)]
 pub fn twa(ts_buffer: TimeSeries, lookback: f64) -> Option<f64> {
     let last_tick = ts_buffer.last()?;
     // NOTE(iy): Should this case be cumultive in behavor? E.g. CMA
@@ -34,7 +34,7 @@ pub fn twa(ts_buffer: TimeSeries, lookback: f64) -> Option<f64> {
         }
         prev_tick = tick;
     }
-    Some((last_tick.price * const_time_diff) / total_time_diff as f64)
+    Some((last_tick.price * const_time_diff) / total_time_diff)
 }
 
 #[cfg(test)]
@@ -42,7 +42,6 @@ mod test {
     use std::sync::Arc;
 
     use super::*;
-    use crate::fill_ts;
     use crate::timeseries::TimeStep;
     use float_cmp::assert_approx_eq;
 